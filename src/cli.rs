@@ -1,5 +1,10 @@
 //! Usage of clap to parse cli parameters
+use crate::{
+    cmd::CleanFlags,
+    common::{tools::parse_duration, types::BuildFlags},
+};
 use clap::{Parser, Subcommand};
+use std::time::Duration;
 use tracing::Level;
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -25,14 +30,26 @@ impl Cli {
     }
 }
 
+/// Profile selected by the `--release`, `--test` and `--profile` flags
+fn profile_name(release: bool, test: bool, profile: &Option<String>) -> Option<String> {
+    if release {
+        Some("release".to_string())
+    } else if test {
+        Some("test".to_string())
+    } else {
+        profile.clone()
+    }
+}
+
 impl BuildSubCommand {
-    pub fn compilation_level(&self) -> usize {
-        if self.release {
-            1
-        } else if self.test {
-            3
-        } else {
-            2
+    pub fn build_flags(&self) -> BuildFlags {
+        BuildFlags {
+            profile: profile_name(self.release, self.test, &self.profile)
+                .unwrap_or_else(|| "dev".to_string()),
+            keep_going: self.keep_going,
+            explain: self.explain,
+            jobs: self.jobs,
+            locked: self.locked,
         }
     }
 }
@@ -40,14 +57,104 @@ impl BuildSubCommand {
 #[derive(Subcommand)]
 pub enum Commands {
     Build(BuildSubCommand),
+    /// Build the project then launch its executable
+    Run(RunSubCommand),
     Test,
-    New { name: String },
+    New {
+        name: String,
+    },
+    /// Remove the outputs of the project or the objects of dependencies
+    Clean(CleanSubCommand),
+    /// Manage the layers, sources and builds in the cache
+    #[clap(subcommand)]
+    Cache(CacheSubCommand),
 }
 
 #[derive(clap::Args)]
-pub struct BuildSubCommand {
+pub struct RunSubCommand {
+    #[clap(flatten)]
+    pub build: BuildSubCommand,
+    /// Arguments given to the executable, after `--`
+    #[clap(last = true)]
+    pub args: Vec<String>,
+}
+
+#[derive(clap::Args)]
+pub struct CleanSubCommand {
+    /// Only remove the outputs of the release build
     #[clap(short, long)]
     pub release: bool,
+    /// Only remove the outputs of the test build
+    #[clap(short, long, conflicts_with = "release")]
+    pub test: bool,
+    /// Only remove the outputs of the given profile
+    #[clap(long, conflicts_with_all = &["release", "test"])]
+    pub profile: Option<String>,
+    /// Remove the cached objects of a dependency, `name` or `name@version`,
+    /// instead of the project outputs
+    #[clap(short, long = "package")]
+    pub packages: Vec<String>,
+    /// Print what would be removed without removing anything
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+impl CleanSubCommand {
+    pub fn clean_flags(&self) -> CleanFlags {
+        CleanFlags {
+            profile: profile_name(self.release, self.test, &self.profile),
+            packages: self.packages.clone(),
+            dry_run: self.dry_run,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum CacheSubCommand {
+    /// List the entries of the cache with their size and last use
+    List,
+    /// Print the folders of a package in the cache
+    Path {
+        /// `name` or `name@version`
+        package: String,
+    },
+    /// Remove the sources and the builds of a package
+    Rm {
+        /// `name` or `name@version`
+        package: String,
+    },
+    /// Remove the entries not used for a while, then the least recently used
+    /// ones if the cache is bigger than the `max_cache_size` setting
+    Prune {
+        /// e.g. `12h`, `30d` or `2w`
+        #[clap(long, parse(try_from_str = parse_duration))]
+        older_than: Option<Duration>,
+    },
+}
+
+#[derive(clap::Args)]
+pub struct BuildSubCommand {
+    /// Build with the release profile
     #[clap(short, long)]
+    pub release: bool,
+    /// Build with the test profile
+    #[clap(short, long, conflicts_with = "release")]
     pub test: bool,
+    /// Build with the given profile, defined in a `[profile.<name>]` section
+    #[clap(long, conflicts_with_all = &["release", "test"])]
+    pub profile: Option<String>,
+    /// Compile every independent unit even if some fail, then report all
+    /// the failures
+    #[clap(short, long)]
+    pub keep_going: bool,
+    /// Print the reason why each file is rebuilt
+    #[clap(long)]
+    pub explain: bool,
+    /// Number of parallel jobs, default to the `jobs` setting or the number
+    /// of CPUs
+    #[clap(short, long)]
+    pub jobs: Option<usize>,
+    /// Fail if `chataigne.lock` is missing or has to be updated
+    #[clap(long)]
+    pub locked: bool,
 }
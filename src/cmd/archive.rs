@@ -0,0 +1,133 @@
+//! Sources of layer packages published as archives, given as
+//! `src = { url = "...", sha256 = "...", strip_prefix = "foo-1.2" }`. The
+//! archive is downloaded, or copied for a local one, checked and extracted
+//! in its folder of the source cache, where its `patches` are applied.
+use super::{
+    cache::touch,
+    git::{apply_patches, patches_key, source_dir},
+    runner,
+};
+use crate::{
+    common::{checksum::hash_file, flock::CacheLock, types::ArchiveTarget},
+    settings::Settings,
+};
+use anyhow::{bail, Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+use tracing::debug;
+
+/// Extract `archive` in `${cache}/src/${name}_${key}` if it isn't already
+/// there, the key identifies the url, the hash, the prefix and the content
+/// of the patches. Return the path of the sources.
+pub fn checkout_archive(
+    name: &str,
+    archive: &ArchiveTarget,
+    settings: &Settings,
+) -> Result<PathBuf> {
+    let key = sha256::digest(format!(
+        "{}#{}#{}{}",
+        archive.url,
+        archive.sha256.to_lowercase(),
+        archive.strip_prefix.as_deref().unwrap_or_default(),
+        patches_key(name, &archive.patches)?
+    ));
+    let dir = source_dir(settings);
+    std::fs::create_dir_all(&dir)?;
+    let dir_name = format!("{name}_{}", &key[..16]);
+    let path = dir.join(&dir_name);
+    if !path.is_dir() {
+        let _lock = CacheLock::acquire(settings, &format!("src_{dir_name}"), &archive.url)?;
+        // another process may have extracted it while we were waiting
+        if !path.is_dir() {
+            let tmp = dir.join(format!(".{dir_name}.{}.tmp", std::process::id()));
+            let result = unpack(archive, &tmp, &path);
+            let _ = std::fs::remove_dir_all(&tmp);
+            result?;
+        }
+    }
+    touch(&path);
+    Ok(path)
+}
+
+/// Download `archive` in the temporary folder `tmp`, check its hash,
+/// extract it there and apply its patches, then move its sources to `dest`.
+/// Nothing is written in `dest` if a step fails.
+pub fn unpack(archive: &ArchiveTarget, tmp: &Path, dest: &Path) -> Result<()> {
+    if tmp.exists() {
+        std::fs::remove_dir_all(tmp)?;
+    }
+    std::fs::create_dir_all(tmp)?;
+    // the name of the archive tells how to extract it
+    let name = archive
+        .url
+        .rsplit('/')
+        .next()
+        .filter(|n| !n.is_empty())
+        .unwrap_or("archive");
+    let file = tmp.join(name);
+    download(&archive.url, &file)?;
+    let hash = hash_file(&file).unwrap_or_default();
+    if !hash.eq_ignore_ascii_case(&archive.sha256) {
+        bail!(
+            "checksum mismatch for {}: expected {}, got {hash}",
+            archive.url,
+            archive.sha256
+        )
+    }
+    let out = tmp.join("out");
+    std::fs::create_dir(&out)?;
+    extract(&file, &out)?;
+    let root = match &archive.strip_prefix {
+        Some(prefix) => out.join(prefix),
+        None => out,
+    };
+    if !root.is_dir() {
+        bail!(
+            "{} not found in the archive {}",
+            archive.strip_prefix.as_deref().unwrap_or_default(),
+            archive.url
+        )
+    }
+    apply_patches(&archive.patches, &archive.url, &root)?;
+    std::fs::rename(&root, dest)?;
+    Ok(())
+}
+
+/// Download `url` with `curl`, or copy it if it's a `file://` url or a path
+fn download(url: &str, file: &Path) -> Result<()> {
+    debug!("download {url} to {}", file.to_string_lossy());
+    let path = match url.strip_prefix("file://") {
+        Some(path) => path,
+        None if url.contains("://") => {
+            let mut cmd = Command::new("curl");
+            cmd.args(["--fail", "--silent", "--show-error", "--location", "-o"])
+                .arg(file)
+                .arg(url);
+            runner::run(cmd, None).with_context(|| format!("unable to download {url}"))?;
+            return Ok(());
+        }
+        None => url,
+    };
+    std::fs::copy(path, file).with_context(|| format!("unable to read the archive {path}"))?;
+    Ok(())
+}
+
+/// Extract the archive `file` into `out`, with `unzip` for a `.zip`, with
+/// `tar` otherwise, which finds the compression itself.
+fn extract(file: &Path, out: &Path) -> Result<()> {
+    let zip = file
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("zip"));
+    let mut cmd;
+    if zip {
+        cmd = Command::new("unzip");
+        cmd.arg("-q").arg(file).arg("-d").arg(out);
+    } else {
+        cmd = Command::new("tar");
+        cmd.arg("-xf").arg(file).arg("-C").arg(out);
+    }
+    runner::run(cmd, Some(file))?;
+    Ok(())
+}
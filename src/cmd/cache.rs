@@ -0,0 +1,289 @@
+//! `ch cache` commands. The cache folder contains:
+//!
+//! ```text
+//! layers/<sha256 of the url>   clones of the git layers, suffixed by
+//!                              `_<key>` when pinned at a revision
+//! src/<name>_<version>_<key>   source checkouts of the layer packages
+//! src/<name>_git_<key>         clones of the git dependencies
+//! build/<name>_<version>       objects of the dependencies
+//! locks/                       lock files of the entries above
+//! ```
+//!
+//! The modification time of an entry is the last time a build used it.
+use crate::{
+    common::{flock::CacheLock, tools::format_size},
+    settings::{Layer, Settings},
+};
+use anyhow::{bail, Result};
+use colored::Colorize;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tracing::debug;
+use walkdir::WalkDir;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Layer,
+    Source,
+    Build,
+}
+
+impl Kind {
+    /// Folder of the entries in the cache
+    fn folder(&self) -> &'static str {
+        match self {
+            Kind::Layer => "layers",
+            Kind::Source => "src",
+            Kind::Build => "build",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Layer => write!(f, "layer"),
+            Kind::Source => write!(f, "source"),
+            Kind::Build => write!(f, "build"),
+        }
+    }
+}
+
+pub struct Entry {
+    pub kind: Kind,
+    /// Package name, or url of a layer
+    pub name: String,
+    /// Version of the package, `git` for a git dependency
+    pub version: Option<String>,
+    pub path: PathBuf,
+    pub size: u64,
+    pub last_use: SystemTime,
+}
+
+impl Entry {
+    fn new(kind: Kind, path: PathBuf, settings: &Settings) -> Self {
+        let dir = path.file_name().unwrap().to_string_lossy().to_string();
+        let (name, version) = match kind {
+            Kind::Layer => (layer_url(&dir, settings).unwrap_or(dir), None),
+            // the key has no `_`, the version neither
+            Kind::Source => match dir.rsplitn(3, '_').collect::<Vec<_>>()[..] {
+                [_, version, name] => (name.to_string(), Some(version.to_string())),
+                _ => (dir, None),
+            },
+            Kind::Build => match dir.rsplit_once('_') {
+                Some((name, version)) => (name.to_string(), Some(version.to_string())),
+                None => (dir, None),
+            },
+        };
+        let size = WalkDir::new(&path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.metadata().ok())
+            .filter(|m| m.is_file())
+            .map(|m| m.len())
+            .sum();
+        let last_use = path
+            .metadata()
+            .and_then(|m| m.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        Self {
+            kind,
+            name,
+            version,
+            path,
+            size,
+            last_use,
+        }
+    }
+
+    /// True if the entry is a package matching `spec`, `name` or
+    /// `name@version`.
+    pub fn matches(&self, spec: &str) -> bool {
+        if self.kind == Kind::Layer {
+            return false;
+        }
+        match spec.split_once('@') {
+            Some((name, version)) => self.name == name && self.version.as_deref() == Some(version),
+            None => self.name == spec,
+        }
+    }
+
+    /// Lock the entry, `None` if `wait` is false and another process holds
+    /// it.
+    fn lock(&self, settings: &Settings, wait: bool) -> Result<Option<CacheLock>> {
+        let prefix = match self.kind {
+            Kind::Layer => "layer",
+            Kind::Source => "src",
+            Kind::Build => "build",
+        };
+        let dir = self.path.file_name().unwrap().to_string_lossy();
+        let name = format!("{prefix}_{dir}");
+        let what = self.path.to_string_lossy();
+        if wait {
+            CacheLock::acquire(settings, &name, &what).map(Some)
+        } else {
+            CacheLock::try_acquire(settings, &name)
+        }
+    }
+
+    /// Remove the entry from the cache, skipped if it's locked and `wait` is
+    /// false. Return true if removed.
+    pub fn remove(&self, settings: &Settings, wait: bool) -> Result<bool> {
+        let _lock = match self.lock(settings, wait)? {
+            Some(lock) => lock,
+            None => return Ok(false),
+        };
+        println!("{} {}", "Removing".green(), self.path.to_string_lossy());
+        std::fs::remove_dir_all(&self.path)?;
+        Ok(true)
+    }
+}
+
+/// Url of the git layer cloned in the folder `dir`
+fn layer_url(dir: &str, settings: &Settings) -> Option<String> {
+    settings.layers.iter().find_map(|l| match l {
+        // a layer pinned at a revision is suffixed by its key
+        Layer::Git(git) if dir.split('_').next() == Some(&sha256::digest(git.git.as_str())) => {
+            Some(git.git.clone())
+        }
+        _ => None,
+    })
+}
+
+/// Mark the cache entry at `path` as used now.
+pub fn touch(path: &Path) {
+    let result = std::fs::File::open(path).and_then(|f| f.set_modified(SystemTime::now()));
+    if let Err(e) = result {
+        debug!("unable to touch {}: {e}", path.to_string_lossy());
+    }
+}
+
+/// All the entries of the cache, ordered by kind and name
+pub fn entries(settings: &Settings) -> Result<Vec<Entry>> {
+    let mut ret = vec![];
+    for kind in [Kind::Layer, Kind::Source, Kind::Build] {
+        let dir = settings.cache_dir.join(kind.folder());
+        let read = match std::fs::read_dir(&dir) {
+            Ok(read) => read,
+            Err(_) => continue,
+        };
+        for entry in read.filter_map(|e| e.ok()) {
+            // skip the clones in progress
+            if entry.file_name().to_string_lossy().starts_with('.') || !entry.path().is_dir() {
+                continue;
+            }
+            ret.push(Entry::new(kind, entry.path(), settings));
+        }
+    }
+    ret.sort_by(|a, b| (a.kind, &a.name, &a.version).cmp(&(b.kind, &b.name, &b.version)));
+    Ok(ret)
+}
+
+/// `ch cache list`
+pub fn list(settings: &Settings) -> Result<()> {
+    let entries = entries(settings)?;
+    let now = SystemTime::now();
+    for e in &entries {
+        let name = match &e.version {
+            Some(version) => format!("{} {version}", e.name),
+            None => e.name.clone(),
+        };
+        let age = now.duration_since(e.last_use).unwrap_or_default();
+        println!(
+            "{:<6} {:<40} {:>10}  {}",
+            e.kind,
+            name,
+            format_size(e.size),
+            format_age(age)
+        );
+    }
+    let total: u64 = entries.iter().map(|e| e.size).sum();
+    println!(
+        "{} entries, {} in {}",
+        entries.len(),
+        format_size(total),
+        settings.cache_dir.to_string_lossy()
+    );
+    Ok(())
+}
+
+/// `ch cache path <pkg>[@version]`
+pub fn path(settings: &Settings, spec: &str) -> Result<()> {
+    let entries = entries(settings)?;
+    let mut found = false;
+    for e in entries.iter().filter(|e| e.matches(spec)) {
+        println!("{}", e.path.to_string_lossy());
+        found = true;
+    }
+    if !found {
+        bail!("{spec} isn't in the cache")
+    }
+    Ok(())
+}
+
+/// `ch cache rm <pkg>[@version]`
+pub fn rm(settings: &Settings, spec: &str) -> Result<()> {
+    let entries = entries(settings)?;
+    let mut found = false;
+    for e in entries.iter().filter(|e| e.matches(spec)) {
+        e.remove(settings, true)?;
+        found = true;
+    }
+    if !found {
+        bail!("{spec} isn't in the cache")
+    }
+    Ok(())
+}
+
+/// `ch cache prune`, remove the entries not used for `older_than`, then the
+/// least recently used ones if the cache is bigger than `max_cache_size`.
+pub fn prune(settings: &Settings, older_than: Option<Duration>) -> Result<()> {
+    if older_than.is_none() && settings.max_cache_size.is_none() {
+        bail!("nothing to prune, give --older-than or set max_cache_size in the settings")
+    }
+    if let Some(older_than) = older_than {
+        let limit = SystemTime::now() - older_than;
+        for e in entries(settings)?.iter().filter(|e| e.last_use < limit) {
+            e.remove(settings, false)?;
+        }
+    }
+    collect_garbage(settings, SystemTime::now())
+}
+
+/// Remove the least recently used entries until the cache fits in the
+/// `max_cache_size` of the settings. Entries used since `since`, by the
+/// current build, and entries locked by another process are kept.
+pub fn collect_garbage(settings: &Settings, since: SystemTime) -> Result<()> {
+    let max = match settings.max_cache_size {
+        Some(max) => max,
+        None => return Ok(()),
+    };
+    let mut entries = entries(settings)?;
+    let mut total: u64 = entries.iter().map(|e| e.size).sum();
+    entries.sort_by_key(|e| e.last_use);
+    for e in entries.iter().take_while(|e| e.last_use < since) {
+        if total <= max {
+            break;
+        }
+        if e.remove(settings, false)? {
+            total -= e.size;
+        }
+    }
+    if total > max {
+        debug!("cache still uses {} after collection", format_size(total));
+    }
+    Ok(())
+}
+
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} minutes ago", secs / 60),
+        3600..=86399 => format!("{} hours ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
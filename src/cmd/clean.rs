@@ -0,0 +1,81 @@
+//! `ch clean`, remove the outputs of the root package or the cached objects
+//! of dependencies.
+use super::{
+    cache::{self, Kind},
+    gcc::output_dir,
+};
+use crate::{common::types::Profile, settings::Settings};
+use anyhow::{bail, Result};
+use colored::Colorize;
+use std::path::{Component, Path, PathBuf};
+
+pub struct CleanFlags {
+    /// Clean only the outputs of this profile
+    pub profile: Option<String>,
+    /// Clean the cached objects of these dependencies instead of the project
+    pub packages: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// Remove `target/`, the outputs of one profile in `target/<profile>`, or
+/// the objects of the given dependencies in the cache so they are rebuilt.
+pub fn clean(settings: &Settings, flags: &CleanFlags) -> Result<()> {
+    if !flags.packages.is_empty() {
+        return clean_packages(settings, flags);
+    }
+    let path = match &flags.profile {
+        Some(profile) => {
+            Profile::check_name(profile)?;
+            let path = output_dir(profile);
+            // only ever remove a folder of `target`
+            let components: Vec<_> = path.components().collect();
+            if !matches!(components[..], [Component::Normal(t), Component::Normal(_)] if t == "target")
+            {
+                bail!("refusing to remove {}", path.to_string_lossy())
+            }
+            path
+        }
+        None => PathBuf::from("target"),
+    };
+    if path.exists() {
+        remove(&path, flags.dry_run)?;
+    }
+    Ok(())
+}
+
+/// Remove the build folders of the `flags.packages` in the cache, all the
+/// versions of a package unless it's given as `name@version`.
+fn clean_packages(settings: &Settings, flags: &CleanFlags) -> Result<()> {
+    let entries = cache::entries(settings)?;
+    for spec in &flags.packages {
+        let builds: Vec<_> = entries
+            .iter()
+            .filter(|e| e.kind == Kind::Build && e.matches(spec))
+            .collect();
+        if builds.is_empty() {
+            bail!("no object of {spec} in the cache")
+        }
+        for e in builds {
+            if flags.dry_run {
+                remove(&e.path, true)?;
+            } else {
+                e.remove(settings, true)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn remove(path: &Path, dry_run: bool) -> Result<()> {
+    if dry_run {
+        println!("{} {}", "Would remove".yellow(), path.to_string_lossy());
+        return Ok(());
+    }
+    println!("{} {}", "Removing".green(), path.to_string_lossy());
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
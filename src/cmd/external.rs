@@ -0,0 +1,413 @@
+//! Libraries built by their own build system (`[lib] build = "cmake"`,
+//! `"make"` or `"autotools"`). The library is configured, built and
+//! installed in its build folder of the cache for the profile, then the
+//! installed headers and libraries are used as for a prebuilt package.
+//!
+//! `make` and `autotools` build in the sources, so these are first copied
+//! in the build folder: the checkout in the cache is shared by the builds
+//! of all the projects.
+use super::{
+    gcc::{LibOutput, Units},
+    runner,
+};
+use crate::common::{
+    checksum::{self, Fingerprint},
+    graph::Node,
+    types::{BuildSystem, DepVal, Flags, LibKind, Package, Profile, SrcVal},
+};
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use walkdir::WalkDir;
+
+/// Headers and flags exported by the dependencies of a library, given to its
+/// build system.
+pub struct Deps<'a> {
+    pub headers: &'a [String],
+    pub flags: &'a [&'a Flags],
+}
+
+impl Deps<'_> {
+    /// `-I` of the headers of the dependencies
+    fn includes(&self) -> String {
+        self.headers
+            .iter()
+            .map(|h| format!("-I{h}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Prefixes where `cmake` finds the packages of the dependencies, the
+    /// folders containing their `include`, e.g. the installation of a
+    /// dependency built with `cmake`.
+    fn prefixes(&self) -> Vec<String> {
+        let mut prefixes: Vec<String> = self
+            .headers
+            .iter()
+            .map(|h| {
+                let path = Path::new(h.trim_end_matches('/'));
+                match (path.file_name(), path.parent()) {
+                    (Some(name), Some(parent)) if name == "include" => parent,
+                    _ => path,
+                }
+                .to_string_lossy()
+                .to_string()
+            })
+            .collect();
+        prefixes.dedup();
+        prefixes
+    }
+}
+
+/// Build the library of the graph `node` from its sources in `dep_path`
+/// with its build system, into `build_dir`, the folder of the `profile`.
+/// The optimization and the options of the profile, the headers and the
+/// flags of its dependencies are given to the build system with the ones
+/// of the `[lib]`.
+/// Return the installed headers and libraries. The output of the commands
+/// is written in `build.log` of the build folder.
+///
+/// The build is skipped if the commands and the build files are the same as
+/// in the previous one: the sources of a layer package never change for a
+/// given revision. The build system of a path dependency or of local
+/// sources is always run, it only rebuilds what changed.
+pub fn build(
+    node: &Node,
+    system: BuildSystem,
+    dep_path: &Path,
+    build_dir: &Path,
+    profile: &Profile,
+    deps: &Deps,
+    units: &Units,
+) -> Result<(Vec<String>, LibOutput)> {
+    let (dependency, package) = (&node.dependency, &node.package);
+    let lib = package.get_lib()?;
+    let install = build_dir.join("install");
+    let work = build_dir.join("src");
+    let (commands, inputs) = match system {
+        BuildSystem::CMake => (
+            cmake(package, profile, deps, dep_path, build_dir, &install)?,
+            vec![dep_path.join("CMakeLists.txt")],
+        ),
+        BuildSystem::Make => (
+            make(package, profile, deps, dep_path, &work, None)?,
+            vec![dep_path.join("Makefile")],
+        ),
+        BuildSystem::Autotools => {
+            let script = ["configure", "configure.ac"]
+                .into_iter()
+                .map(|f| dep_path.join(f))
+                .find(|f| f.exists());
+            let script = match script {
+                Some(script) => script,
+                None => bail!(
+                    "no configure script in {} for the autotools build of {}",
+                    dep_path.to_string_lossy(),
+                    package.pkg_description.name
+                ),
+            };
+            (
+                make(package, profile, deps, dep_path, &work, Some(&install))?,
+                vec![script],
+            )
+        }
+    };
+    // the sources are part of the command, another checkout or other
+    // patches are rebuilt
+    let command = std::iter::once(format!("cd {}", dep_path.to_string_lossy()))
+        .chain(commands.iter().map(runner::render))
+        .collect::<Vec<_>>()
+        .join(" && ");
+    let stamp = build_dir.join("install.stamp");
+    let reason = match (
+        &dependency.desc,
+        checksum::stale(&stamp, &command, units.compiler()),
+    ) {
+        (_, Some(reason)) => Some(reason),
+        (DepVal::Path(_), None) => Some(String::from("path dependency")),
+        (_, None) if matches!(package.pkg_description.src, Some(SrcVal::Local(_))) => {
+            Some(String::from("local sources"))
+        }
+        (_, None) => None,
+    };
+    if let Some(reason) = reason {
+        let desc = &package.pkg_description;
+        println!("{} {} {}", "Compiling".green(), desc.name, desc.version);
+        if units.explain() {
+            println!(
+                "{} {}: {reason}",
+                "Rebuilding".cyan(),
+                dep_path.to_string_lossy()
+            );
+        }
+        let _ = std::fs::remove_file(checksum::fingerprint_path(&stamp));
+        std::fs::create_dir_all(build_dir)?;
+        if system != BuildSystem::CMake {
+            sync(dep_path, &work)?;
+        }
+        let log = build_dir.join("build.log");
+        std::fs::write(&log, "")?;
+        for mut cmd in commands {
+            // share the jobserver with the build tool
+            units.configure_make(&mut cmd);
+            run_logged(cmd, dep_path, &log).with_context(|| {
+                format!(
+                    "build of {} failed, see {}",
+                    desc.name,
+                    log.to_string_lossy()
+                )
+            })?;
+        }
+        std::fs::write(&stamp, "")?;
+        Fingerprint::new(&command, units.compiler(), inputs)?.save(&stamp)?;
+    }
+    let (libraries, headers) = match system {
+        // nothing is installed, the headers are in the sources
+        BuildSystem::Make => {
+            let headers = match lib.headers.is_empty() {
+                true => vec![work.clone()],
+                false => lib.headers.iter().map(|h| work.join(h)).collect(),
+            };
+            (libraries(&work, None, lib.kind), headers)
+        }
+        _ => (
+            libraries(&install.join("lib"), Some(1), lib.kind),
+            vec![install.join("include")],
+        ),
+    };
+    if libraries.is_empty() {
+        bail!(
+            "no library built by {} in {}",
+            package.pkg_description.name,
+            build_dir.to_string_lossy()
+        )
+    }
+    let kind = match libraries[0].extension() {
+        Some(e) if e == "a" => LibKind::Static,
+        _ => LibKind::Shared,
+    };
+    let headers = headers
+        .iter()
+        .map(|h| h.to_string_lossy().to_string())
+        .collect();
+    Ok((headers, LibOutput::Libraries(libraries, kind)))
+}
+
+/// Configure, build and install commands of a `cmake` library, with the
+/// build type of the `profile`. The flags are given as `CMAKE_C_FLAGS` and
+/// `CMAKE_CXX_FLAGS`.
+fn cmake(
+    package: &Package,
+    profile: &Profile,
+    deps: &Deps,
+    dep_path: &Path,
+    build_dir: &Path,
+    install: &Path,
+) -> Result<Vec<Command>> {
+    let lib = package.get_lib()?;
+    let binary_dir = build_dir.join("cmake");
+    let shared = if lib.kind == LibKind::Shared {
+        "ON"
+    } else {
+        "OFF"
+    };
+    let mut configure = Command::new("cmake");
+    configure
+        .arg("-S")
+        .arg(dep_path)
+        .arg("-B")
+        .arg(&binary_dir)
+        .arg(format!("-DCMAKE_BUILD_TYPE={}", profile.cmake_build_type()))
+        .arg(format!(
+            "-DCMAKE_INSTALL_PREFIX={}",
+            install.to_string_lossy()
+        ))
+        .arg("-DCMAKE_INSTALL_LIBDIR=lib")
+        .arg(format!("-DBUILD_SHARED_LIBS={shared}"))
+        .arg(format!("-DCMAKE_POSITION_INDEPENDENT_CODE={shared}"))
+        .arg(format!(
+            "-DCMAKE_C_FLAGS={} {}",
+            compile_flags(package, profile, deps, "c")?,
+            deps.includes()
+        ))
+        .arg(format!(
+            "-DCMAKE_CXX_FLAGS={} {}",
+            compile_flags(package, profile, deps, "cpp")?,
+            deps.includes()
+        ));
+    let prefixes = deps.prefixes();
+    if !prefixes.is_empty() {
+        configure.arg(format!("-DCMAKE_PREFIX_PATH={}", prefixes.join(";")));
+    }
+    let mut variables: Vec<_> = lib.cmake.variables.iter().collect();
+    variables.sort();
+    for (name, value) in variables {
+        configure.arg(format!("-D{name}={value}"));
+    }
+    let mut build = Command::new("cmake");
+    build.arg("--build").arg(&binary_dir);
+    if !lib.cmake.targets.is_empty() {
+        build.arg("--target").args(&lib.cmake.targets);
+    }
+    let mut install_cmd = Command::new("cmake");
+    install_cmd.arg("--install").arg(&binary_dir);
+    Ok(vec![configure, build, install_cmd])
+}
+
+/// Commands of a `make` library built in `work`, a copy of its sources in
+/// `dep_path`, or of an `autotools` one installed in `install`. The
+/// compiler and the flags are given as `CC`, `CXX`, `CFLAGS`, `CXXFLAGS`
+/// and `CPPFLAGS` in the environment and in the arguments of `make`.
+fn make(
+    package: &Package,
+    profile: &Profile,
+    deps: &Deps,
+    dep_path: &Path,
+    work: &Path,
+    install: Option<&Path>,
+) -> Result<Vec<Command>> {
+    let lib = package.get_lib()?;
+    let env = [
+        ("CC", String::from("gcc")),
+        ("CXX", String::from("g++")),
+        ("CFLAGS", compile_flags(package, profile, deps, "c")?),
+        ("CXXFLAGS", compile_flags(package, profile, deps, "cpp")?),
+        ("CPPFLAGS", deps.includes()),
+    ];
+    let command = |program: &str| {
+        let mut cmd = Command::new(program);
+        cmd.current_dir(work).envs(env.iter().map(|(k, v)| (k, v)));
+        cmd
+    };
+    let mut commands = vec![];
+    if let Some(install) = install {
+        // `work` is only a copy of the sources once the build runs
+        if !dep_path.join("configure").exists() && dep_path.join("configure.ac").exists() {
+            let mut autoreconf = command("autoreconf");
+            autoreconf.arg("--install");
+            commands.push(autoreconf);
+        }
+        let mut configure = command("./configure");
+        configure
+            .arg(format!("--prefix={}", install.to_string_lossy()))
+            .arg(format!(
+                "--libdir={}",
+                install.join("lib").to_string_lossy()
+            ))
+            .args(&lib.make.configure);
+        commands.push(configure);
+    }
+    // the variables of the command line override the ones set by a Makefile
+    let variables: Vec<_> = env.iter().map(|(k, v)| format!("{k}={v}")).collect();
+    let mut build = command("make");
+    build.args(&variables).args(&lib.make.targets);
+    commands.push(build);
+    if install.is_some() {
+        let mut install = command("make");
+        install.args(&variables).arg("install");
+        commands.push(install);
+    }
+    Ok(commands)
+}
+
+/// Flags of the `profile`, of the `[lib]` and the ones exported by its
+/// dependencies to compile a file with the extension `ext`, with `-fPIC`
+/// for a shared library.
+fn compile_flags(package: &Package, profile: &Profile, deps: &Deps, ext: &str) -> Result<String> {
+    let lib = package.get_lib()?;
+    let src = PathBuf::from(format!("lib.{ext}"));
+    let mut args = profile.dependency_args(&src);
+    args.extend(lib.flags.compile_args(&src, true));
+    for flags in deps.flags {
+        args.extend(flags.compile_args(&src, false));
+    }
+    if lib.kind == LibKind::Shared {
+        args.push(String::from("-fPIC"));
+    }
+    Ok(args.join(" "))
+}
+
+/// Run a step of the build, its command and output are appended to `log`.
+/// On failure, the output is also in the error.
+fn run_logged(cmd: Command, dep_path: &Path, log: &Path) -> Result<()> {
+    let mut file = OpenOptions::new().append(true).create(true).open(log)?;
+    writeln!(file, "$ {}", runner::render(&cmd))?;
+    match runner::run(cmd, Some(dep_path)) {
+        Ok(output) => {
+            file.write_all(&output.stdout)?;
+            file.write_all(&output.stderr)?;
+            Ok(())
+        }
+        Err(e) => {
+            write!(file, "{}{}", e.stdout, e.stderr)?;
+            Err(e.into())
+        }
+    }
+}
+
+/// Copy the sources in `src` to `dest`, without the `.git` folder. Files
+/// keep their modification time and the ones already up to date aren't
+/// copied, so `make` only rebuilds what changed.
+fn sync(src: &Path, dest: &Path) -> Result<()> {
+    let walk = WalkDir::new(src)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git");
+    for entry in walk {
+        let entry = entry?;
+        let target = dest.join(entry.path().strip_prefix(src)?);
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else if entry.path_is_symlink() {
+            let _ = std::fs::remove_file(&target);
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+        } else {
+            let modified = meta.modified()?;
+            let same = target.symlink_metadata().is_ok_and(|t| {
+                t.is_file() && t.len() == meta.len() && t.modified().ok() == Some(modified)
+            });
+            if !same {
+                let _ = std::fs::remove_file(&target);
+                std::fs::copy(entry.path(), &target).with_context(|| {
+                    format!("unable to copy {}", entry.path().to_string_lossy())
+                })?;
+                std::fs::File::open(&target)?.set_modified(modified)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Libraries in `dir`, in its subfolders up to `depth`, the static ones
+/// unless `kind` is shared or there are only shared ones. Sorted, so the
+/// link is the same at each build.
+fn libraries(dir: &Path, depth: Option<usize>, kind: LibKind) -> Vec<PathBuf> {
+    let mut archives = vec![];
+    let mut shared = vec![];
+    let mut walk = WalkDir::new(dir);
+    if let Some(depth) = depth {
+        walk = walk.max_depth(depth);
+    }
+    for entry in walk.into_iter().filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with("lib") || entry.file_type().is_dir() {
+            continue;
+        }
+        if name.ends_with(".a") {
+            archives.push(entry.into_path());
+        } else if name.ends_with(".so") {
+            shared.push(entry.into_path());
+        }
+    }
+    archives.sort();
+    shared.sort();
+    match (kind, archives.is_empty(), shared.is_empty()) {
+        (LibKind::Shared, _, false) | (_, true, _) => shared,
+        _ => archives,
+    }
+}
@@ -1,187 +1,773 @@
 // todo, use tool in settings
-// todo, dump in target.
-// todo, if `path` dependencies, compute a checksum to not rebuild
 // todo, if input arg `rebuild`, force build anyway
 // todo, replace unwraps with error management
 use crate::{
-    cmd::git::checkout_dependency,
+    cmd::{
+        cache::{self, touch},
+        external,
+        git::{checkout_dependency, source_dir},
+        jobs::Scheduler,
+        runner::{self, BuildErrors},
+    },
     common::{
-        tools::{self, find_pkg},
-        types::{DepVal, Dependency, Package},
+        checksum::{self, Fingerprint},
+        flock::CacheLock,
+        graph::{DepGraph, Node},
+        lockfile::{self, LockFile},
+        tools,
+        types::{BuildFlags, DepVal, Flags, LibKind, Package, Prebuilt, Profile, SrcVal},
     },
     settings::Settings,
 };
-use anyhow::Result;
+use anyhow::{bail, Result};
 use colored::Colorize;
-use std::{collections::HashSet, path::PathBuf, process::Command, sync::Once};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    time::SystemTime,
+};
 use tracing::debug;
 
-pub fn compile(package: &Package, settings: &Settings, compile_level: usize) -> Result<()> {
-    let mut headers = vec![];
-    let mut opts = package.get_opt(compile_level);
-    let mut dependencies = package.get_dependencies(compile_level);
+/// What a dependency gives to the link
+pub enum LibOutput {
+    /// Objects to archive, or to link into a shared library
+    Objects(Vec<PathBuf>),
+    /// Libraries not built by chataigne, prebuilt or built by the build
+    /// system of the package
+    Libraries(Vec<PathBuf>, LibKind),
+}
+
+/// A stale compilation unit waiting to be run.
+struct Job {
+    cmd: Command,
+    /// Rendered command line, part of the fingerprint
+    command: String,
+    src: PathBuf,
+    obj: PathBuf,
+}
+
+/// Compilation units of a build. Decide which units are stale using the
+/// fingerprints, then run them in parallel with the [Scheduler]. Without
+/// `--keep-going` no unit is started after a failure, otherwise all of them
+/// run and the failures are reported together. In both cases, the steps
+/// depending on the units (link) aren't run.
+pub struct Units {
+    scheduler: Scheduler,
+    explain: bool,
+    /// Output of `gcc --version`, part of every fingerprint
+    compiler: String,
+    /// Packages for which we already printed the `Compiling` line
+    announced: HashSet<String>,
+    queue: Vec<Job>,
+}
+
+impl Units {
+    /// Output of `gcc --version`
+    pub fn compiler(&self) -> &str {
+        &self.compiler
+    }
+
+    /// True if the reason of each rebuild is printed
+    pub fn explain(&self) -> bool {
+        self.explain
+    }
+
+    /// Give to a `make` command the access to the jobserver of the build
+    pub fn configure_make(&self, cmd: &mut Command) {
+        self.scheduler.configure_make(cmd);
+    }
+
+    fn new(flags: &BuildFlags, settings: &Settings) -> Result<Self> {
+        let mut cmd = Command::new("gcc");
+        cmd.arg("--version");
+        let version = runner::run(cmd, None)?;
+        let jobs = match flags.jobs.or(settings.jobs) {
+            Some(jobs) => jobs,
+            None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+        Ok(Self {
+            scheduler: Scheduler::new(jobs, flags.keep_going)?,
+            explain: flags.explain,
+            compiler: String::from_utf8_lossy(&version.stdout).to_string(),
+            announced: HashSet::new(),
+            queue: vec![],
+        })
+    }
+
+    /// Queue the compilation of the unit `src` of `package` into `obj` with
+    /// the given `cmd` if its fingerprint is stale. The object is written to
+    /// a temporary file, renamed once gcc succeeded.
+    fn push(&mut self, package: &Package, mut cmd: Command, src: &Path, obj: &Path) {
+        cmd.arg("-o").arg(checksum::temp_path(obj));
+        cmd.arg("-MMD").arg("-MF").arg(checksum::depfile_path(obj));
+        let command = runner::render(&cmd);
+        let reason = match checksum::stale(obj, &command, &self.compiler) {
+            Some(reason) => reason,
+            None => return,
+        };
+        let name = &package.pkg_description.name;
+        if self.announced.insert(name.clone()) {
+            println!(
+                "{} {} {}",
+                "Compiling".green(),
+                name,
+                package.pkg_description.version
+            );
+        }
+        if self.explain {
+            println!(
+                "{} {}: {reason}",
+                "Rebuilding".cyan(),
+                src.to_string_lossy()
+            );
+        }
+        self.queue.push(Job {
+            cmd,
+            command,
+            src: src.to_path_buf(),
+            obj: obj.to_path_buf(),
+        });
+    }
+
+    /// Run all the queued units. The output of each unit is printed in one
+    /// block when it ends. Return an error with all the failures, if any.
+    fn run(&mut self) -> Result<()> {
+        let jobs = std::mem::take(&mut self.queue);
+        let mut errors = self.scheduler.run(jobs, |mut job| {
+            // share the jobserver with gcc, used by `-flto=jobserver`
+            self.scheduler.configure_make(&mut job.cmd);
+            // the previous fingerprint doesn't describe the object anymore
+            let _ = std::fs::remove_file(checksum::fingerprint_path(&job.obj));
+            match runner::run(job.cmd, Some(&job.src)) {
+                Ok(output) => {
+                    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+                    std::fs::rename(checksum::temp_path(&job.obj), &job.obj)?;
+                    let depfile = std::fs::read_to_string(checksum::depfile_path(&job.obj))?;
+                    let inputs = checksum::parse_depfile(&depfile);
+                    Fingerprint::new(&job.command, &self.compiler, inputs)?.save(&job.obj)
+                }
+                Err(e) => {
+                    eprintln!("{} {}", "Failed".red().bold(), job.src.to_string_lossy());
+                    Err(e.into())
+                }
+            }
+        });
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => bail!(BuildErrors(errors)),
+        }
+    }
+}
+
+pub fn compile(package: &Package, settings: &Settings, flags: &BuildFlags) -> Result<()> {
+    let profile = package.profile(&flags.profile)?;
+    let start = SystemTime::now();
+    let mut units = Units::new(flags, settings)?;
     debug!("Start compilation of {}", package.pkg_description.name);
-    debug!("Package options {:?}", opts);
-    // todo: some paralelisation can be done here,
-    // 1. separate git clones and compilation
-    // 2. if checksums ok, compile all in paralel, otherwise check what we
-    //    need to recompile
-    while !dependencies.is_empty() {
-        let dependency = dependencies.pop_back().unwrap();
-        let pkg_file = find_pkg(&dependency, settings)?;
-        let (h, o) = compile_lib(&dependency, &pkg_file, settings)?;
-        headers.extend(h);
-        opts.extend(o);
-        dependencies.extend(pkg_file.get_dependencies(compile_level));
-    }
-
-    let objects = compile_pkg(package, headers, opts.clone(), compile_level)?;
-    link(&package.pkg_description.name, &opts, objects);
+    let manifest = lockfile::manifest_hash(package)?;
+    let previous = LockFile::load(package, &manifest)?;
+    let reused = previous.as_ref();
+    let pinned;
+    let settings = match reused {
+        Some(lock) => {
+            pinned = lock.pin_layers(settings);
+            &pinned
+        }
+        None => settings,
+    };
+    let mut graph = DepGraph::build(package, settings, &profile.name, reused)?;
+    if let Some(lock) = reused {
+        lock.pin_sources(&mut graph.nodes);
+    }
+    let mut locks = lock_build_dirs(&graph, settings)?;
+    // Units are only queued here, they are all compiled in parallel before
+    // the link. Dependencies come first in the graph, so the headers and
+    // the flags of all the dependencies of a library are known when we
+    // reach it.
+    let mut exported_headers: Vec<Vec<String>> = vec![];
+    let mut lib_flags: Vec<&Flags> = vec![];
+    let mut lib_outputs = vec![];
+    let mut locked = vec![];
+    for (i, node) in graph.nodes.iter().enumerate() {
+        let deps = graph.transitive_deps(i);
+        let dep_headers: Vec<String> = deps
+            .iter()
+            .flat_map(|d| exported_headers[*d].iter().cloned())
+            .collect();
+        let dep_flags: Vec<&Flags> = deps.iter().map(|d| lib_flags[*d]).collect();
+        let (dep_path, source_lock) = checkout_locked(node, settings)?;
+        locks.extend(source_lock);
+        locked.push(lockfile::locked_package(node, &dep_path, settings)?);
+        let (h, output) = compile_lib(
+            node,
+            &dep_path,
+            settings,
+            &profile,
+            &dep_headers,
+            &dep_flags,
+            &mut units,
+        )?;
+        exported_headers.push(h);
+        lib_flags.push(&node.package.get_lib()?.flags);
+        lib_outputs.push(output);
+    }
+    lockfile::update(package, manifest, locked, previous, flags.locked)?;
+
+    let headers = exported_headers.into_iter().flatten().collect();
+    let objects = compile_pkg(package, headers, &lib_flags, &profile, &mut units)?;
+    units.run()?;
+    // the shared libraries are copied next to the executable
+    let rpath = Flags {
+        ldflags: vec!["-Wl,-rpath,$ORIGIN/lib".to_string()],
+        ..Default::default()
+    };
+    let mut link_flags = vec![&profile.flags];
+    let mut shared = vec![];
+    let mut inputs: Vec<Vec<PathBuf>> = objects.into_iter().map(|o| vec![o]).collect();
+    for i in graph.link_order() {
+        let node = &graph.nodes[i];
+        let libs = match (&lib_outputs[i], node.package.get_lib()?.kind) {
+            (LibOutput::Libraries(libs, LibKind::Shared), _) => {
+                for lib in libs {
+                    shared.push((library_soname(lib), lib.clone()));
+                }
+                libs.clone()
+            }
+            (LibOutput::Libraries(libs, _), _) => libs.clone(),
+            (LibOutput::Objects(_), LibKind::HeaderOnly | LibKind::System) => vec![],
+            (LibOutput::Objects(objs), LibKind::Static) => {
+                archive(&node.package, objs, &units, flags, settings)?
+                    .into_iter()
+                    .collect()
+            }
+            (LibOutput::Objects(objs), LibKind::Shared) => {
+                let lib = link_shared(&node.package, objs, &units, flags, settings)?;
+                if let Some(lib) = &lib {
+                    shared.push((soname(&node.package), lib.clone()));
+                }
+                lib.into_iter().collect()
+            }
+        };
+        if !libs.is_empty() {
+            inputs.push(libs);
+        }
+        link_flags.push(lib_flags[i]);
+    }
+    if !shared.is_empty() {
+        link_flags.push(&rpath);
+    }
+    link(
+        &package.pkg_description.name,
+        &link_flags,
+        inputs,
+        &units,
+        flags,
+    )?;
+    install_shared(&flags.profile, &shared)?;
+    drop(locks);
+    cache::collect_garbage(settings, start)?;
     println!("{}", "Finishing".green());
     Ok(())
 }
 
-/// Run a compilation with the test dependencies, use the `[test]` object in
-/// the settings to modify the build options, sources etc. (Nothing to do by
-/// default) and launch the output.
+/// Checkout the sources of the graph `node` and lock them until the end of
+/// the build when they are in the source cache, so they aren't removed
+/// while they are compiled. The build folder of the package is already
+/// locked, no other build waits for these sources at the same time.
+fn checkout_locked(node: &Node, settings: &Settings) -> Result<(PathBuf, Option<CacheLock>)> {
+    // only git and archive sources are checked out in the cache
+    let cached = match (&node.dependency.desc, &node.package.pkg_description.src) {
+        (DepVal::Git(_), _) => true,
+        (DepVal::Version(_), Some(src)) => !matches!(src, SrcVal::Local(_)),
+        _ => false,
+    };
+    for _ in 0..2 {
+        let path = checkout_dependency(&node.dependency, &node.package, settings)?;
+        if !cached || !path.starts_with(source_dir(settings)) {
+            return Ok((path, None));
+        }
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let lock = CacheLock::acquire(settings, &format!("src_{name}"), &name)?;
+        // removed by another process before the lock, checked out again
+        if path.is_dir() {
+            return Ok((path, Some(lock)));
+        }
+    }
+    bail!(
+        "sources of {} removed from the cache during the build",
+        node.dependency.name
+    )
+}
+
+/// Lock the build folders of all the dependencies in the cache until the end
+/// of the build. They are locked in the same order by all the processes, so
+/// two builds can't wait for each other.
+fn lock_build_dirs(graph: &DepGraph, settings: &Settings) -> Result<Vec<CacheLock>> {
+    let mut dirs: Vec<PathBuf> = graph
+        .nodes
+        .iter()
+        .filter(|n| !matches!(n.dependency.desc, DepVal::System(_)))
+        .map(|n| n.package.target_dir(settings))
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    let mut locks = vec![];
+    for dir in dirs {
+        let name = dir.file_name().unwrap().to_string_lossy().to_string();
+        locks.push(CacheLock::acquire(
+            settings,
+            &format!("build_{name}"),
+            &name,
+        )?);
+        std::fs::create_dir_all(&dir)?;
+        touch(&dir);
+    }
+    Ok(locks)
+}
+
+/// Run a compilation with the `test` profile, use the `[profile.test]`
+/// object in the package file to modify the build options, sources etc.
+/// (Nothing to do by default) and launch the output.
 pub fn test(package: &Package, settings: &Settings) -> Result<()> {
     let n = package.pkg_description.name.clone();
-    compile(package, settings, 3)?;
-    let c = Command::new(format!("./target/{n}")).spawn()?;
-    c.wait_with_output()?;
+    let flags = BuildFlags {
+        profile: "test".to_string(),
+        keep_going: false,
+        explain: false,
+        jobs: None,
+        locked: false,
+    };
+    compile(package, settings, &flags)?;
+    let status = execute("test", &n, &[])?;
+    if !status.success() {
+        bail!("tests of {n} failed ({status})")
+    }
     Ok(())
 }
 
-/// Compile library for a static linking. Return a tuple containing a list of
-/// headers (folders containing) and a list of options that the main program
-/// need to compile.
-pub fn compile_lib(
-    dependency: &Dependency,
+/// `ch run`, build the package with the profile of the `flags` and launch the
+/// executable with the given `args`.
+pub fn run(
     package: &Package,
     settings: &Settings,
-) -> Result<(HashSet<String>, HashSet<String>)> {
+    flags: &BuildFlags,
+    args: &[String],
+) -> Result<()> {
+    let n = &package.pkg_description.name;
+    compile(package, settings, flags)?;
+    println!(
+        "{} {}",
+        "Running".green(),
+        binary_path(&flags.profile, n).to_string_lossy()
+    );
+    let status = execute(&flags.profile, n, args)?;
+    if !status.success() {
+        bail!("{n} failed ({status})")
+    }
+    Ok(())
+}
+
+/// Launch the executable of the root package built with `profile`. Its
+/// shared libraries are also found through `LD_LIBRARY_PATH`, in case the
+/// executable is moved or its rpath ignored.
+fn execute(profile: &str, package_name: &str, args: &[String]) -> Result<ExitStatus> {
+    let mut paths = vec![std::env::current_dir()?.join(shared_dir(profile))];
+    if let Some(previous) = std::env::var_os("LD_LIBRARY_PATH") {
+        paths.extend(std::env::split_paths(&previous));
+    }
+    let status = Command::new(Path::new(".").join(binary_path(profile, package_name)))
+        .args(args)
+        .env("LD_LIBRARY_PATH", std::env::join_paths(paths)?)
+        .status()?;
+    Ok(status)
+}
+
+/// Compile the library of the graph `node` for a static or shared linking
+/// from its sources checked out in `dep_path`. `dep_headers` and
+/// `dep_flags` are the headers and the flags exported by the dependencies
+/// of the library. Return a tuple containing a list of headers (folders
+/// containing) and the objects to link, or the libraries themselves if the
+/// package is prebuilt or has its own build system. Nothing is compiled for
+/// a header-only library.
+pub fn compile_lib(
+    node: &Node,
+    dep_path: &Path,
+    settings: &Settings,
+    profile: &Profile,
+    dep_headers: &[String],
+    dep_flags: &[&Flags],
+    units: &mut Units,
+) -> Result<(Vec<String>, LibOutput)> {
+    let (dependency, package) = (&node.dependency, &node.package);
     debug!("compile lib {}", dependency.name);
-    let mut headers = HashSet::new();
-    let mut opts = HashSet::new();
+    let mut objects = vec![];
     let lib = package.get_lib()?;
-    let dep_path = checkout_dependency(dependency, package, settings)?;
+    if let Some(prebuilt) = &lib.prebuilt {
+        return use_prebuilt(package, prebuilt, dep_path);
+    }
+    if let Some(system) = lib.build {
+        let build_dir = package.profile_dir(settings, &profile.name);
+        let deps = external::Deps {
+            headers: dep_headers,
+            flags: dep_flags,
+        };
+        return external::build(node, system, dep_path, &build_dir, profile, &deps, units);
+    }
+    if lib.kind == LibKind::System {
+        return Ok((vec![], LibOutput::Objects(objects)));
+    }
+    if lib.kind == LibKind::HeaderOnly && !lib.headers.is_empty() {
+        // nothing to look for, the folders are given relative to the sources
+        let headers = lib.headers.iter().map(|h| tools::concat(dep_path, h));
+        return Ok((headers.collect(), LibOutput::Objects(objects)));
+    }
     debug!("compile lib from path {}", dep_path.to_string_lossy());
-    let pkg_paths = package.lib_package_path(&dep_path)?;
+    let pkg_paths = package.lib_package_path(dep_path)?;
+    // todo, create a diff between `headers` and `exports`
+    let headers: Vec<String> = pkg_paths
+        .header_folders
+        .iter()
+        .map(|h| tools::concat(dep_path, &h.to_string_lossy()))
+        .collect();
+    if lib.kind == LibKind::HeaderOnly {
+        return Ok((headers, LibOutput::Objects(objects)));
+    }
 
-    let once = Once::new();
     for src in &pkg_paths.source_files {
         let mut cmd = Command::new("gcc");
-        let output = package.object_path(src, settings);
-        let output_str = output.to_str().unwrap().to_string();
-        cmd.args(vec!["-o", &output_str]);
-        opts.insert(output_str);
-        debug!("option {:?}", &lib.opt);
-        opts.extend(lib.opt.iter().cloned());
-        cmd.args(&lib.opt);
+        let output = package.object_path(src, settings, &profile.name);
+        if lib.kind == LibKind::Shared {
+            cmd.arg("-fPIC");
+        }
+        cmd.args(profile.dependency_args(src));
+        cmd.args(lib.flags.compile_args(src, true));
+        for flags in dep_flags {
+            cmd.args(flags.compile_args(src, false));
+        }
         cmd.arg("-c");
-        // todo, create a diff between `headers` and `exports`
-        for h in &pkg_paths.header_folders {
-            let h = tools::concat(&dep_path, &h.to_string_lossy());
-            cmd.arg("-I").arg(&h);
-            headers.insert(h);
+        for h in headers.iter().chain(dep_headers) {
+            cmd.arg("-I").arg(h);
         }
-        cmd.arg(tools::concat(&dep_path, &src.to_string_lossy()));
-        match &dependency.desc {
-            DepVal::Version(_) => {
-                if output.is_file() {
-                    continue;
-                }
-            }
-            DepVal::Git(_) => todo!("git dependencies are not ready"),
-            _ => {} // todo: don't compile if checksum unchanged
-        };
+        let src = PathBuf::from(tools::concat(dep_path, &src.to_string_lossy()));
+        cmd.arg(&src);
+        units.push(package, cmd, &src, &output);
+        objects.push(output);
+    }
+    Ok((headers, LibOutput::Objects(objects)))
+}
 
-        once.call_once(|| {
-            println!(
-                "{} {} {}",
-                "Compiling".green(),
-                package.pkg_description.name,
-                package.pkg_description.version
+/// Headers and library of a prebuilt package checked out in `dep_path`, for
+/// the current architecture. The library must exist and match its sha256
+/// when one is given.
+fn use_prebuilt(
+    package: &Package,
+    prebuilt: &Prebuilt,
+    dep_path: &Path,
+) -> Result<(Vec<String>, LibOutput)> {
+    let name = &package.pkg_description.name;
+    let arch = std::env::consts::ARCH;
+    let lib = package.get_lib()?;
+    let prebuilt = prebuilt.for_arch(arch);
+    let (file, kind) = match prebuilt.library(lib.kind) {
+        Some(library) => library,
+        None => bail!("no prebuilt library of {name} for {arch}"),
+    };
+    let path = dep_path.join(file);
+    if !path.is_file() {
+        bail!(
+            "prebuilt library of {name} not found at {}",
+            path.to_string_lossy()
+        )
+    }
+    if let Some(expected) = &prebuilt.sha256 {
+        let actual = checksum::hash_file(&path).unwrap_or_default();
+        if !actual.eq_ignore_ascii_case(expected) {
+            bail!(
+                "prebuilt library {} of {name} doesn't match its sha256, expected {expected} but got {actual}",
+                path.to_string_lossy()
             )
-        });
-
-        internal_run(cmd);
+        }
     }
-    Ok((headers, opts))
+    let headers = prebuilt
+        .headers
+        .as_ref()
+        .unwrap_or(&lib.headers)
+        .iter()
+        .map(|h| tools::concat(dep_path, h))
+        .collect();
+    Ok((headers, LibOutput::Libraries(vec![path], kind)))
 }
 
 /// Compilation of a package given all static library `headers` dependencies
 /// without links.
 ///
-/// Take in input the `headers` (what we need to include) that had been deduced
-/// when the dependencies has been build. We also give the `opts`
+/// Take in input the `headers` (what we need to include) and the flags of
+/// the libraries (their `public` ones are used) that had been deduced when
+/// the dependencies has been build.
 pub fn compile_pkg(
     package: &Package,
     headers: Vec<String>,
-    mut opts: Vec<String>,
-    compile_level: usize,
+    lib_flags: &[&Flags],
+    profile: &Profile,
+    units: &mut Units,
 ) -> Result<Vec<PathBuf>> {
-    opts.extend(
-        headers
-            .iter()
-            .flat_map(|h| vec![String::from("-I"), h.clone()]),
-    );
-    let paths = package.root_package_paths(compile_level)?;
+    let paths = package.root_package_paths(profile)?;
     let mut objects = vec![];
-    let opts = package.get_opt(compile_level);
     for src in &paths.source_files {
         let mut cmd = Command::new("gcc");
-        cmd.args(&opts);
+        cmd.args(profile.compile_args(src));
+        for flags in lib_flags {
+            cmd.args(flags.compile_args(src, false));
+        }
         cmd.args(
             paths
                 .header_folders
                 .iter()
-                .flat_map(|h| vec![String::from("-I"), h.to_string_lossy().to_string()])
+                .map(|h| h.to_string_lossy().to_string())
+                .chain(headers.iter().cloned())
+                .flat_map(|h| vec![String::from("-I"), h])
                 .collect::<Vec<String>>(),
         );
-        let mut output = PathBuf::from("./target");
-        output.push(src);
-        let obj_path = output.with_extension("o");
-        output.pop();
-        std::fs::create_dir_all(output).unwrap();
-        cmd.args(vec![
-            "-c",
-            &src.to_string_lossy(),
-            "-o",
-            &obj_path.to_string_lossy(),
-        ]);
-        cmd.args(&opts);
+        let obj_path = object_path(&profile.name, src);
+        std::fs::create_dir_all(obj_path.parent().unwrap())?;
+        cmd.args(vec!["-c", &src.to_string_lossy()]);
+        units.push(package, cmd, src, &obj_path);
         objects.push(obj_path);
-        // todo: don't compile if checksum unchanged
-        println!(
-            "{} {} {}",
-            "Compiling".green(),
-            package.pkg_description.name,
-            package.pkg_description.version,
-        );
-        internal_run(cmd);
     }
     Ok(objects)
 }
 
-/// Latest part of he compilation is linking all dependencies, .o files and
-/// shared lib links. All requirements already are field in `opts` when we
-/// builded dependencies.
-pub fn link(package_name: &str, opts: &Vec<String>, objects: Vec<PathBuf>) {
-    let mut cmd = Command::new("gcc");
-    cmd.current_dir(std::env::current_dir().unwrap())
-        .args(opts)
-        .args(vec!["-o", &format!("target/{package_name}")])
-        .args(objects.iter().map(|p| p.to_string_lossy().to_string()));
-    internal_run(cmd);
-}
-
-/// Tooling, launch given command line
-fn internal_run(mut cmd: Command) {
-    debug!("run: {:?}", cmd);
-    let c = cmd.spawn().unwrap();
-    c.wait_with_output().unwrap();
-    //if !c.wait_with_output().unwrap().status.success() {
-    //    panic!("{}", "compile error".red().bold())
-    //}
+/// Output folder of the root package, `target/<profile>`. Each profile has
+/// its own objects, fingerprints and executable.
+pub fn output_dir(profile: &str) -> PathBuf {
+    Path::new("target").join(profile)
+}
+
+/// Object built from the source `src` of the root package
+pub fn object_path(profile: &str, src: &Path) -> PathBuf {
+    output_dir(profile).join(src).with_extension("o")
+}
+
+/// Folder of the shared libraries loaded by the executable of the root
+/// package, `target/<profile>/lib`
+pub fn shared_dir(profile: &str) -> PathBuf {
+    output_dir(profile).join("lib")
+}
+
+/// Executable of the root package
+pub fn binary_path(profile: &str, package_name: &str) -> PathBuf {
+    output_dir(profile).join(package_name)
+}
+
+/// Path of the static library of a dependency, `lib<name>.a` in its build
+/// folder of the `profile`
+pub fn archive_path(package: &Package, settings: &Settings, profile: &str) -> PathBuf {
+    package
+        .profile_dir(settings, profile)
+        .join(format!("lib{}.a", package.pkg_description.name))
+}
+
+/// Archive the `objects` of a dependency into its static library, so the
+/// linker only pulls the objects it needs. Return the library, `None` if the
+/// dependency has no object.
+///
+/// The archive is deterministic (`D`) and skipped if the objects are the same
+/// as in the previous archive.
+fn archive(
+    package: &Package,
+    objects: &[PathBuf],
+    units: &Units,
+    flags: &BuildFlags,
+    settings: &Settings,
+) -> Result<Option<PathBuf>> {
+    if objects.is_empty() {
+        return Ok(None);
+    }
+    let output = archive_path(package, settings, &flags.profile);
+    let cmd = archive_command(&output, objects);
+    produce(&output, cmd, objects.to_vec(), "Archiving", units, flags)?;
+    Ok(Some(output))
+}
+
+/// Command archiving `objects` into the temporary file of `output`. The
+/// `D` modifier leaves no timestamps in the archive, so the same objects
+/// always give the same archive.
+pub fn archive_command(output: &Path, objects: &[PathBuf]) -> Command {
+    let mut cmd = Command::new("ar");
+    cmd.arg("rcsD")
+        .arg(checksum::temp_path(output))
+        .args(objects);
+    cmd
+}
+
+/// Path of the shared library of a dependency, `lib<name>.so` in its build
+/// folder of the `profile`
+pub fn shared_path(package: &Package, settings: &Settings, profile: &str) -> PathBuf {
+    package
+        .profile_dir(settings, profile)
+        .join(format!("lib{}.so", package.pkg_description.name))
+}
+
+/// Name of the shared library of `package` recorded in the executables. It
+/// changes with the compatibility of the version: `lib<name>.so.<major>`, or
+/// `lib<name>.so.0.<minor>` before 1.0.0.
+pub fn soname(package: &Package) -> String {
+    let desc = &package.pkg_description;
+    let compat = match semver::Version::parse(&desc.version) {
+        Ok(v) if v.major == 0 => format!("0.{}", v.minor),
+        Ok(v) => v.major.to_string(),
+        Err(_) => desc.version.clone(),
+    };
+    format!("lib{}.so.{compat}", desc.name)
+}
+
+/// Name of a shared library not built by chataigne recorded in the
+/// executables, read with `objdump`, the name of the file if it has no
+/// soname.
+fn library_soname(lib: &Path) -> String {
+    let file_name = lib.file_name().unwrap().to_string_lossy().to_string();
+    let mut cmd = Command::new("objdump");
+    cmd.arg("-p").arg(lib);
+    let output = match runner::run(cmd, Some(lib)) {
+        Ok(output) => output,
+        Err(e) => {
+            debug!(
+                "unable to read the soname of {}: {e}",
+                lib.to_string_lossy()
+            );
+            return file_name;
+        }
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("SONAME"))
+        .map(|soname| soname.trim().to_string())
+        .unwrap_or(file_name)
+}
+
+/// Link the `objects` of a dependency, compiled with `-fPIC`, into its shared
+/// library. The symbols of its own dependencies are resolved with the
+/// executable. Return the library, `None` if the dependency has no object.
+fn link_shared(
+    package: &Package,
+    objects: &[PathBuf],
+    units: &Units,
+    flags: &BuildFlags,
+    settings: &Settings,
+) -> Result<Option<PathBuf>> {
+    if objects.is_empty() {
+        return Ok(None);
+    }
+    let output = shared_path(package, settings, &flags.profile);
+    let lib = package.get_lib()?;
+    let mut cmd = Command::new("g++");
+    cmd.arg("-shared")
+        .arg(format!("-Wl,-soname,{}", soname(package)))
+        .args(lib.flags.link_args())
+        .arg("-o")
+        .arg(checksum::temp_path(&output))
+        .args(objects)
+        .args(lib.flags.lib_args());
+    produce(&output, cmd, objects.to_vec(), "Relinking", units, flags)?;
+    Ok(Some(output))
+}
+
+/// Copy the `(soname, path)` shared libraries into `target/<profile>/lib`,
+/// where the executable loads them from.
+fn install_shared(profile: &str, shared: &[(String, PathBuf)]) -> Result<()> {
+    if shared.is_empty() {
+        return Ok(());
+    }
+    let dir = shared_dir(profile);
+    std::fs::create_dir_all(&dir)?;
+    for (soname, lib) in shared {
+        let dest = dir.join(soname);
+        std::fs::copy(lib, checksum::temp_path(&dest))?;
+        std::fs::rename(checksum::temp_path(&dest), &dest)?;
+    }
+    Ok(())
+}
+
+/// Run `cmd` writing `output` from the `inputs` if the fingerprint of the
+/// output is stale. `cmd` writes into the temporary path of the output,
+/// renamed when it succeeded.
+fn produce(
+    output: &Path,
+    cmd: Command,
+    inputs: Vec<PathBuf>,
+    verb: &str,
+    units: &Units,
+    flags: &BuildFlags,
+) -> Result<()> {
+    let command = runner::render(&cmd);
+    let reason = match checksum::stale(output, &command, &units.compiler) {
+        Some(reason) => reason,
+        None => return Ok(()),
+    };
+    if flags.explain {
+        println!("{} {}: {reason}", verb.cyan(), output.to_string_lossy());
+    }
+    let temp = checksum::temp_path(output);
+    let _ = std::fs::remove_file(checksum::fingerprint_path(output));
+    // `ar r` would add the objects to a leftover archive
+    let _ = std::fs::remove_file(&temp);
+    let out = runner::run(cmd, Some(output))?;
+    eprint!("{}", String::from_utf8_lossy(&out.stderr));
+    std::fs::rename(&temp, output)?;
+    Fingerprint::new(&command, &units.compiler, inputs)?.save(output)
+}
+
+/// Latest part of he compilation is linking all dependencies, the .o files of
+/// the package then the static or shared libraries of the dependencies, each
+/// before the libraries it depends on. Each of the `inputs` is a group of
+/// files, the libraries of a group can depend on each other. `flags` are the
+/// flags of the root package then of the libraries in link order, their link
+/// flags go before the objects and their libs after them.
+///
+/// The link is skipped if the command line and all the objects are the same
+/// as in the previous link.
+pub fn link(
+    package_name: &str,
+    flags: &[&Flags],
+    inputs: Vec<Vec<PathBuf>>,
+    units: &Units,
+    build_flags: &BuildFlags,
+) -> Result<()> {
+    let output = binary_path(&build_flags.profile, package_name);
+    let cmd = link_command(&output, flags, &inputs)?;
+    let inputs = inputs.into_iter().flatten().collect();
+    produce(&output, cmd, inputs, "Relinking", units, build_flags)
+}
+
+/// Command linking the `inputs` into the temporary file of `output`, see
+/// [link]. The same flags and inputs always give the same command line.
+pub fn link_command(output: &Path, flags: &[&Flags], inputs: &[Vec<PathBuf>]) -> Result<Command> {
+    let mut ldflags: Vec<String> = vec![];
+    for f in flags {
+        for arg in f.link_args() {
+            if !ldflags.contains(&arg) {
+                ldflags.push(arg);
+            }
+        }
+    }
+    let libs = Flags::link_libs(flags);
+    // g++ brings the c++ standard library in the link
+    let mut cmd = Command::new("g++");
+    cmd.current_dir(std::env::current_dir()?)
+        .args(ldflags)
+        .arg("-o")
+        .arg(checksum::temp_path(output));
+    for group in inputs {
+        if group.len() > 1 {
+            cmd.arg("-Wl,--start-group")
+                .args(group)
+                .arg("-Wl,--end-group");
+        } else {
+            cmd.args(group);
+        }
+    }
+    cmd.args(libs);
+    Ok(cmd)
 }
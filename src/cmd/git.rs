@@ -1,10 +1,16 @@
-use super::internal_run;
+use super::{archive::checkout_archive, cache::touch, runner};
 use crate::{
-    common::types::{DepVal, Dependency, Package, SrcVal},
+    common::{
+        checksum::hash_file,
+        flock::CacheLock,
+        tools::get_layer_directory,
+        types::{DepVal, Dependency, GitTarget, LocalTarget, Package, SrcVal},
+    },
     settings::Settings,
 };
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use std::{
+    fmt,
     path::{Path, PathBuf},
     process::Command,
 };
@@ -13,8 +19,17 @@ use tracing::debug;
 /// Checkout source folder if needed from `Src` object.
 ///
 /// - `git` param is Some, checkout git repository if not in cache
-/// - `commit` param is Some, process command `git checkout $param`
+/// - `commit`, `rev`, `tag` or `branch` param is Some, checkout it
 /// - `path` param is Some, print an error if `git` is set (incompatible)
+/// - `url` param is Some, extract the archive, see [checkout_archive]
+/// - `path` param is Some, use the sources on disk, see [local_source]
+///
+/// The sources of a layer package are checked out in
+/// `${cache}/src/${name}_${version}_${key}`, where the key identifies the
+/// repository and the revision. A checkout never changes once done, so
+/// several versions or revisions of a package can be used at the same time.
+/// A layer package without `src` ships its files next to its package file,
+/// the folder of the package file is returned.
 ///
 /// Return an error if git not found or any command failed.
 ///
@@ -28,44 +43,372 @@ pub fn checkout_dependency(
         // if version, clone git repository
         DepVal::Version(_) => {
             // todo: force reload with a parameter, also add a `clear` param
-            let mut dep_path = settings.project_dirs.cache_dir().to_path_buf();
-            dep_path.push(&dependency.name);
-            match &package.pkg_description.src {
-                Some(SrcVal::Local(_)) => {
-                    todo!("checkout local dependencies will be implemented soon")
-                }
-                Some(SrcVal::Git(src)) => git_clone(&src.git, &src.commit, &dep_path),
-                None => {} // nothing to do
-            };
-            Ok(dep_path)
+            let desc = &package.pkg_description;
+            let name = format!("{}_{}", desc.name, desc.version);
+            match &desc.src {
+                Some(SrcVal::Local(local)) => local_source(package, local, settings),
+                Some(SrcVal::Git(src)) => checkout_source(&name, src, settings),
+                Some(SrcVal::Archive(archive)) => checkout_archive(&name, archive, settings),
+                None => package_folder(package),
+            }
+        }
+        // the objects of a path dependency are shared by all the projects
+        // using it, whatever the relative path they give
+        DepVal::Path(path) => std::fs::canonicalize(path)
+            .with_context(|| format!("path dependency {} not found", path.to_string_lossy())),
+        DepVal::Git(git) => checkout_git_dependency(&dependency.name, git, settings),
+        // installed on the system, nothing to checkout
+        DepVal::System(_) => Ok(PathBuf::new()),
+    }
+}
+
+/// Clone the repository of the git dependency `name` in
+/// `${cache}/src/${name}_git_${key}` and checkout its revision. The repository contains
+/// the `chataigne.toml` of the dependency and its sources, as a path
+/// dependency. Return the path of the repository.
+pub fn checkout_git_dependency(
+    name: &str,
+    git: &GitTarget,
+    settings: &Settings,
+) -> Result<PathBuf> {
+    checkout_source(&format!("{name}_git"), git, settings)
+}
+
+/// Folder of the sources on disk of a layer package, given by
+/// `src = { path = "..." }`. A relative path is resolved from the folder of
+/// the package file, then from the root of its layer. Nothing is copied, the
+/// objects are written in the build folder of the package in the cache.
+fn local_source(package: &Package, local: &LocalTarget, settings: &Settings) -> Result<PathBuf> {
+    let path = Path::new(&local.path);
+    let mut candidates = vec![];
+    if path.is_absolute() {
+        candidates.push(path.to_path_buf());
+    } else {
+        if let Some(dir) = package.file.parent() {
+            candidates.push(dir.join(path));
+        }
+        for layer in &settings.layers {
+            let root = get_layer_directory(layer, settings)?;
+            if package.file.starts_with(&root) {
+                candidates.push(root.join(path));
+            }
         }
-        DepVal::Path(path) => Ok(path.to_owned()),
-        _ => bail!(
-            "cannot get path dependency of {}",
-            package.pkg_description.name
+    }
+    match candidates.iter().find(|p| p.is_dir()) {
+        Some(dir) => Ok(std::fs::canonicalize(dir)?),
+        None => bail!(
+            "sources of {} not found at {}, relative to {} or to the root of its layer",
+            package.pkg_description.name,
+            local.path,
+            package.file.to_string_lossy()
         ),
     }
+}
 
-    // todo, implement for other depvals
-    // todo manage each case in specific function, implement checkout in another file.
+/// Folder of the package file of `package`, where a layer package without
+/// sources keeps its files
+fn package_folder(package: &Package) -> Result<PathBuf> {
+    match package.file.parent() {
+        Some(dir) => Ok(std::fs::canonicalize(dir)?),
+        None => bail!(
+            "no folder for the package file {}",
+            package.file.to_string_lossy()
+        ),
+    }
 }
 
-/// Clone a repository into `dest`, optionnaly checkout something, a tag, a
-/// commit...
-pub fn git_clone(url: &str, checkout: &Option<String>, dest_path: &Path) {
-    debug!("check repository at {}", dest_path.to_string_lossy());
-    let dest = dest_path.file_name().unwrap().to_string_lossy();
-    let parent = dest_path.parent().unwrap();
-    if !dest_path.is_dir() {
-        debug!("clone repository {url} at {}", dest_path.to_string_lossy());
+/// Folder of the source checkouts in the cache
+pub fn source_dir(settings: &Settings) -> PathBuf {
+    settings.cache_dir.join("src")
+}
+
+/// Checkout `git` in its own folder of the source cache, prefixed by
+/// `name`, if it isn't already there, and apply its patches. The content of
+/// the patches is part of the key, so patched sources have their own
+/// folder and are rebuilt when a patch changes.
+fn checkout_source(name: &str, git: &GitTarget, settings: &Settings) -> Result<PathBuf> {
+    let dir_name = source_dir_name(name, git)?;
+    let dir = source_dir(settings);
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(&dir_name);
+    if !path.is_dir() {
+        let _lock = CacheLock::acquire(settings, &format!("src_{dir_name}"), &git.git)?;
+        // another process may have checked it out while we were waiting
+        if !path.is_dir() && git.patches.is_empty() {
+            git_fetch(git, &path)?;
+        } else if !path.is_dir() {
+            // patched aside, the folder never contains unpatched sources
+            let tmp = dir.join(format!(".{dir_name}.{}.patch", std::process::id()));
+            let result =
+                git_fetch(git, &tmp).and_then(|_| apply_patches(&git.patches, &git.git, &tmp));
+            if let Err(e) = result.and_then(|_| Ok(std::fs::rename(&tmp, &path)?)) {
+                let _ = std::fs::remove_dir_all(&tmp);
+                return Err(e);
+            }
+        }
+    }
+    touch(&path);
+    Ok(path)
+}
+
+/// Name of the folder of the checkout of `git` in the source cache,
+/// `${name}_${key}` where the key identifies the repository, the revision
+/// and the content of the patches.
+pub fn source_dir_name(name: &str, git: &GitTarget) -> Result<String> {
+    let key = format!("{}#{}", git.git, Revision::of(git)?);
+    let key = sha256::digest(key + &patches_key(name, &git.patches)?);
+    Ok(format!("{name}_{}", &key[..16]))
+}
+
+/// Part of the key of a source folder given by the content of its
+/// `patches`, empty without patches
+pub fn patches_key(name: &str, patches: &[PathBuf]) -> Result<String> {
+    let mut key = String::new();
+    for patch in patches {
+        match hash_file(patch) {
+            Some(hash) => key = format!("{key}+{hash}"),
+            None => bail!(
+                "unable to read the patch {} of {name}",
+                patch.to_string_lossy()
+            ),
+        }
+    }
+    Ok(key)
+}
+
+/// Apply the `patches` in order to the sources at `path`, checked out from
+/// `origin`. A patch is applied entirely or not at all.
+pub fn apply_patches(patches: &[PathBuf], origin: &str, path: &Path) -> Result<()> {
+    for patch in patches {
+        let absolute = std::fs::canonicalize(patch)
+            .with_context(|| format!("patch {} not found", patch.to_string_lossy()))?;
+        debug!("apply {} to {origin}", absolute.to_string_lossy());
         let mut cmd = Command::new("git");
-        cmd.args(vec!["clone", url, &dest]).current_dir(parent);
-        internal_run(cmd);
+        cmd.arg("apply").arg(&absolute).current_dir(path);
+        // extracted sources aren't a repository, don't look for one above
+        if let Some(parent) = path.parent() {
+            cmd.env("GIT_CEILING_DIRECTORIES", parent);
+        }
+        runner::run(cmd, Some(patch)).with_context(|| {
+            format!(
+                "the patch {} doesn't apply to {origin}, update it for this revision",
+                patch.to_string_lossy()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Revision of a repository to checkout, given by a [GitTarget]
+#[derive(Debug, PartialEq, Eq)]
+pub enum Revision {
+    /// Default branch of the repository
+    Head,
+    /// Commit, tag or branch given by the `commit` key
+    Any(String),
+    Rev(String),
+    Tag(String),
+    Branch(String),
+}
+
+impl Revision {
+    pub fn of(target: &GitTarget) -> Result<Self> {
+        let mut revisions = [
+            target.commit.clone().map(Revision::Any),
+            target.rev.clone().map(Revision::Rev),
+            target.tag.clone().map(Revision::Tag),
+            target.branch.clone().map(Revision::Branch),
+        ]
+        .into_iter()
+        .flatten();
+        match (revisions.next(), revisions.next()) {
+            (None, _) => Ok(Revision::Head),
+            (Some(revision), None) => Ok(revision),
+            _ => bail!(
+                "only one of commit, rev, tag and branch can be given for {}",
+                target.git
+            ),
+        }
     }
-    if let Some(h) = checkout {
-        debug!("repository cloned, checkout {h}");
+
+    /// Names of the revision in a clone, tried in order. A branch of the
+    /// remote comes before a local one, which can be outdated.
+    fn names(&self) -> Vec<String> {
+        match self {
+            Revision::Head => vec!["HEAD".to_string()],
+            Revision::Any(r) => vec![format!("refs/remotes/origin/{r}"), r.clone()],
+            Revision::Rev(r) => vec![r.clone()],
+            Revision::Tag(t) => vec![format!("refs/tags/{t}")],
+            Revision::Branch(b) => vec![format!("refs/remotes/origin/{b}")],
+        }
+    }
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Revision::Head => write!(f, "HEAD"),
+            Revision::Any(r) | Revision::Rev(r) => write!(f, "{r}"),
+            Revision::Tag(t) => write!(f, "tag {t}"),
+            Revision::Branch(b) => write!(f, "branch {b}"),
+        }
+    }
+}
+
+/// Same target as `target` at the given `commit`, e.g. a commit of the lock.
+pub fn pinned(target: &GitTarget, commit: &str) -> GitTarget {
+    GitTarget {
+        git: target.git.clone(),
+        rev: Some(commit.to_string()),
+        shallow: target.shallow,
+        submodules: target.submodules,
+        patches: target.patches.clone(),
+        ..Default::default()
+    }
+}
+
+/// Clone the repository of `target` into `dest` if it's missing and
+/// checkout its revision. An existing clone fetches the revision only if
+/// it doesn't know it yet, so a branch isn't updated while it exists.
+///
+/// The clone is done in a temporary folder renamed into `dest` once the
+/// revision is checked out, a failed clone leaves nothing behind.
+///
+/// Return an error with the git output if a command failed.
+pub fn git_fetch(target: &GitTarget, dest: &Path) -> Result<()> {
+    debug!("check repository at {}", dest.to_string_lossy());
+    let revision = Revision::of(target)?;
+    if dest.is_dir() {
+        return update(target, &revision, dest);
+    }
+    let tmp = dest.with_file_name(format!(
+        ".{}.{}.tmp",
+        dest.file_name().unwrap().to_string_lossy(),
+        std::process::id()
+    ));
+    if tmp.exists() {
+        std::fs::remove_dir_all(&tmp)?;
+    }
+    debug!(
+        "clone repository {} at {}",
+        target.git,
+        dest.to_string_lossy()
+    );
+    let result = clone(target, &revision, &tmp).and_then(|_| update(target, &revision, &tmp));
+    if let Err(e) = result {
+        let _ = std::fs::remove_dir_all(&tmp);
+        return Err(e);
+    }
+    if let Err(e) = std::fs::rename(&tmp, dest) {
+        std::fs::remove_dir_all(&tmp)?;
+        // another process may have cloned it in the meantime
+        if !dest.is_dir() {
+            bail!("unable to move the clone of {}: {e}", target.git)
+        }
+    }
+    Ok(())
+}
+
+fn clone(target: &GitTarget, revision: &Revision, dest: &Path) -> Result<()> {
+    let mut cmd = Command::new("git");
+    cmd.args(["clone", "--quiet"]);
+    if target.shallow {
+        match revision {
+            Revision::Head => cmd.arg("--depth=1"),
+            Revision::Tag(name) | Revision::Branch(name) => {
+                cmd.args(["--depth=1", "--branch", name])
+            }
+            // the history is needed to find the commit, only skip the files
+            Revision::Any(_) | Revision::Rev(_) => cmd.arg("--filter=blob:none"),
+        };
+    }
+    cmd.arg(&target.git).arg(dest);
+    runner::run(cmd, Some(dest))?;
+    Ok(())
+}
+
+/// Fetch the revision if it's missing, check it out and update the
+/// submodules.
+fn update(target: &GitTarget, revision: &Revision, path: &Path) -> Result<()> {
+    let commit = match resolve(revision, path) {
+        Some(commit) => commit,
+        None => {
+            fetch(target, revision, path)
+                .with_context(|| format!("unable to fetch {revision} from {}", target.git))?;
+            match resolve(revision, path) {
+                Some(commit) => commit,
+                None => bail!("{revision} not found in {}", target.git),
+            }
+        }
+    };
+    if git_head(path)? != commit {
+        debug!(
+            "checkout {revision} ({commit}) in {}",
+            path.to_string_lossy()
+        );
+        let mut cmd = Command::new("git");
+        cmd.args(["checkout", "--quiet", "--detach", &commit])
+            .current_dir(path);
+        runner::run(cmd, Some(path))?;
+    }
+    if target.submodules {
+        let mut cmd = Command::new("git");
+        cmd.args(["submodule", "update", "--init", "--recursive"])
+            .current_dir(path);
+        if target.shallow {
+            cmd.arg("--depth=1");
+        }
+        runner::run(cmd, Some(path))?;
+    }
+    Ok(())
+}
+
+/// Commit of the `revision` in the clone at `path`, `None` if unknown.
+fn resolve(revision: &Revision, path: &Path) -> Option<String> {
+    revision.names().iter().find_map(|name| {
         let mut cmd = Command::new("git");
-        cmd.args(vec!["checkout", h]).current_dir(dest_path);
-        internal_run(cmd);
+        cmd.args([
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{name}^{{commit}}"),
+        ])
+        .current_dir(path);
+        let output = runner::run(cmd, Some(path)).ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    })
+}
+
+fn fetch(target: &GitTarget, revision: &Revision, path: &Path) -> Result<()> {
+    debug!("fetch {revision} from {}", target.git);
+    let mut cmd = Command::new("git");
+    cmd.args(["fetch", "--quiet", "origin"]).current_dir(path);
+    match revision {
+        Revision::Tag(t) => {
+            cmd.arg(format!("+refs/tags/{t}:refs/tags/{t}"));
+        }
+        Revision::Branch(b) => {
+            cmd.arg(format!("+refs/heads/{b}:refs/remotes/origin/{b}"));
+        }
+        Revision::Head | Revision::Any(_) | Revision::Rev(_) => {
+            // a commit can be anywhere in the history
+            if path.join(".git").join("shallow").exists() {
+                cmd.arg("--unshallow");
+            }
+            cmd.args(["--tags", "+refs/heads/*:refs/remotes/origin/*"]);
+        }
+    }
+    if target.shallow && matches!(revision, Revision::Tag(_) | Revision::Branch(_)) {
+        cmd.arg("--depth=1");
     }
+    runner::run(cmd, Some(path))?;
+    Ok(())
+}
+
+/// Return the commit checked out in the repository at `path`.
+pub fn git_head(path: &Path) -> Result<String> {
+    let mut cmd = Command::new("git");
+    cmd.args(vec!["rev-parse", "HEAD"]).current_dir(path);
+    let output = runner::run(cmd, Some(path))?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
@@ -0,0 +1,79 @@
+//! Run independent jobs in parallel, bounded by a GNU make jobserver.
+//!
+//! Each running job holds a token of the jobserver. Makefile-based children
+//! configured with [Scheduler::configure_make] take their extra tokens from
+//! the same pool, so the whole build respects the `-j` limit.
+use anyhow::Result;
+use jobserver::Client;
+use std::{
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+use tracing::debug;
+
+pub struct Scheduler {
+    client: Client,
+    jobs: usize,
+    keep_going: bool,
+}
+
+impl Scheduler {
+    /// Create a scheduler running at most `jobs` jobs at the same time. When
+    /// `keep_going` is false, no job is started after a failure.
+    pub fn new(jobs: usize, keep_going: bool) -> Result<Self> {
+        let jobs = jobs.max(1);
+        debug!("start a jobserver with {jobs} tokens");
+        Ok(Self {
+            client: Client::new(jobs)?,
+            jobs,
+            keep_going,
+        })
+    }
+
+    /// Give to a `make` command the access to the jobserver.
+    pub fn configure_make(&self, cmd: &mut Command) {
+        self.client.configure_make(cmd);
+    }
+
+    /// Run `f` on each task in parallel and return the errors. Tasks don't
+    /// depend on each other, a step that needs their results has to wait the
+    /// end of this function.
+    pub fn run<T, F>(&self, tasks: Vec<T>, f: F) -> Vec<anyhow::Error>
+    where
+        T: Send,
+        F: Fn(T) -> Result<()> + Sync,
+    {
+        let workers = self.jobs.min(tasks.len());
+        let queue = Mutex::new(tasks.into_iter());
+        let errors = Mutex::new(vec![]);
+        let stop = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            for _ in 0..workers {
+                s.spawn(|| loop {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let task = match queue.lock().unwrap().next() {
+                        Some(task) => task,
+                        None => break,
+                    };
+                    let result = self
+                        .client
+                        .acquire()
+                        .map_err(anyhow::Error::from)
+                        .and_then(|_token| f(task));
+                    if let Err(e) = result {
+                        if !self.keep_going {
+                            stop.store(true, Ordering::SeqCst);
+                        }
+                        errors.lock().unwrap().push(e);
+                    }
+                });
+            }
+        });
+        errors.into_inner().unwrap()
+    }
+}
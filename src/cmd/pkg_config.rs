@@ -0,0 +1,107 @@
+//! System libraries found with `pkg-config`, given in the dependencies as
+//! `openssl = { system = "openssl", version = ">=1.1" }`.
+use super::runner;
+use crate::common::types::{
+    Flag, Flags, LibKind, Package, PkgDescription, Scope, StaticLib, SystemTarget,
+};
+use anyhow::{bail, Context, Result};
+use std::{collections::HashMap, path::PathBuf, process::Command};
+
+/// Package of the system library of the dependency `name`. Nothing is built,
+/// the `--cflags` of the library are given to the packages using it and its
+/// `--libs` to the link. Fail if the library, or a version matching the
+/// requirement, isn't installed.
+pub fn system_package(name: &str, target: &SystemTarget) -> Result<Package> {
+    let module = &target.system;
+    let mut specs = vec![module.clone()];
+    if let Some(version) = &target.version {
+        specs = module_specs(module, version)?;
+    }
+    let mut cmd = Command::new("pkg-config");
+    cmd.args(["--exists", "--print-errors"]).args(&specs);
+    pkg_config(cmd).with_context(|| {
+        format!(
+            "system library {} of {name} not found, install it or set PKG_CONFIG_PATH",
+            specs.join(" ")
+        )
+    })?;
+    let query = |arg: &str| {
+        let mut cmd = Command::new("pkg-config");
+        cmd.arg(arg).arg(module);
+        pkg_config(cmd).with_context(|| format!("unable to read {arg} of {module}"))
+    };
+    let version = query("--modversion")?.trim().to_string();
+    let flags = Flags {
+        cflags: split(&query("--cflags")?)
+            .map(|value| Flag {
+                value,
+                scope: Scope::Public,
+            })
+            .collect(),
+        libs: split(&query("--libs")?).collect(),
+        ..Default::default()
+    };
+    Ok(Package {
+        file: PathBuf::new(),
+        pkg_description: PkgDescription {
+            name: name.to_string(),
+            version,
+            description: None,
+            src: None,
+            repostory: None,
+        },
+        dependencies: None,
+        flags: Flags::default(),
+        ignore: vec![],
+        profiles: HashMap::new(),
+        lib: Some(StaticLib {
+            kind: LibKind::System,
+            prebuilt: None,
+            build: None,
+            cmake: Default::default(),
+            make: Default::default(),
+            headers: vec![],
+            builds: vec![],
+            flags,
+            ignore: vec![],
+        }),
+        sources: vec![],
+        includes: vec![],
+    })
+}
+
+/// Modules given to `pkg-config` for the `module` with the `version`
+/// requirement, e.g. `>=1.1, <4` gives `module >= 1.1` and `module < 4`.
+/// The versions are compared by `pkg-config`, only its operators are
+/// accepted.
+pub fn module_specs(module: &str, version: &str) -> Result<Vec<String>> {
+    let mut specs = vec![];
+    for req in version.split(',').map(str::trim) {
+        let op = ["==", ">=", "<=", "!=", "=", ">", "<"]
+            .into_iter()
+            .find(|op| req.starts_with(op));
+        let (op, version) = match op {
+            Some(op) => (op, req[op.len()..].trim()),
+            None => bail!(
+                "invalid version requirement {req:?} of the system library {module}, \
+                 use one of =, !=, >, >=, < or <= followed by a version"
+            ),
+        };
+        if version.is_empty() {
+            bail!("missing version after {op} in the requirement of the system library {module}")
+        }
+        let op = if op == "==" { "=" } else { op };
+        specs.push(format!("{module} {op} {version}"));
+    }
+    Ok(specs)
+}
+
+/// Run `pkg-config`, return its output
+fn pkg_config(cmd: Command) -> Result<String> {
+    let output = runner::run(cmd, None)?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn split(flags: &str) -> impl Iterator<Item = String> + '_ {
+    flags.split_whitespace().map(str::to_string)
+}
@@ -0,0 +1,140 @@
+//! Run the external tools (gcc, ar, git...) with a captured output and turn
+//! their failures into typed errors readable by the final user.
+use colored::Colorize;
+use std::{
+    fmt,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    sync::Mutex,
+};
+use tracing::debug;
+
+/// Error returned when an external command cannot be spawned or exits with a
+/// failure status. It keeps everything we need to print a diagnostic: the
+/// command line, the file it was working on and the captured output.
+#[derive(Debug)]
+pub struct CommandError {
+    /// Rendered command line, ready to be copied in a terminal
+    pub command: String,
+    /// File processed by the command (translation unit, archive, repository)
+    pub file: Option<PathBuf>,
+    /// Exit code of the process, `None` if killed or never started
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "failed to process {}", file.to_string_lossy())?,
+            None => write!(f, "command failed")?,
+        }
+        match self.status {
+            Some(code) => writeln!(f, " (exit code {code})")?,
+            None => writeln!(f)?,
+        }
+        writeln!(f, "  {} {}", "command:".bold(), self.command)?;
+        for out in [&self.stdout, &self.stderr] {
+            if !out.trim().is_empty() {
+                writeln!(f, "{}", out.trim_end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// All the failures collected during a build run with `--keep-going`, or
+/// by jobs running at the same time.
+#[derive(Debug)]
+pub struct BuildErrors(pub Vec<anyhow::Error>);
+
+impl fmt::Display for BuildErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} unit(s) failed to build", self.0.len())?;
+        for e in &self.0 {
+            write!(f, "\n{e:#}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BuildErrors {}
+
+/// Processes launched by [run] and still running
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Kill the processes launched by [run] that are still running, and their
+/// children, called when the build is interrupted.
+pub fn kill_children() {
+    for pid in CHILDREN.lock().unwrap().drain(..) {
+        debug!("kill {pid}");
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGTERM);
+        }
+    }
+}
+
+/// Render a command line as a user would type it in a shell.
+pub fn render(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|a| {
+            let a = a.to_string_lossy();
+            if a.is_empty() || a.contains(char::is_whitespace) {
+                format!("'{a}'")
+            } else {
+                a.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Launch the given command line, wait for it and capture its output. The
+/// `file` is the one processed by the command, it's only used to give a
+/// better context in the error.
+pub fn run(mut cmd: Command, file: Option<&Path>) -> Result<Output, CommandError> {
+    debug!("run: {:?}", cmd);
+    let command = render(&cmd);
+    let file = file.map(Path::to_path_buf);
+    // in its own process group, so all its children are killed with it
+    let output = cmd
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|child| {
+            let pid = child.id();
+            CHILDREN.lock().unwrap().push(pid);
+            let output = child.wait_with_output();
+            CHILDREN.lock().unwrap().retain(|p| *p != pid);
+            output
+        });
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            return Err(CommandError {
+                command,
+                file,
+                status: None,
+                stdout: String::new(),
+                stderr: format!("unable to launch the command: {e}"),
+            })
+        }
+    };
+    if output.status.success() {
+        return Ok(output);
+    }
+    Err(CommandError {
+        command,
+        file,
+        status: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
@@ -1,32 +1,167 @@
-/*
-todo: use checksum for local dependencies
-
-use std::path::Path;
-use config::{Config, File};
-
-/// Check sum of dependency headers and sources
-#[derive(Deserialize)]
-pub struct CheckSum {
-    #[serde(default)]
-    pub(super) headers: Vec<u32>,
-    #[serde(default)]
-    pub(super) sources: Vec<u32>,
-}
-
-pub fn checksum() {}
-
-impl CheckSum {
-    pub fn load(dep_path: &Path) -> Self {
-        let mut cfg = Config::default();
-        let mut cs_file = dep_path.to_path_buf();
-        cs_file.push("cs.toml");
-        match cfg.merge(File::from(cs_file)) {
-            Ok(cs) => return cs.try_into(),
-            Err(_) => todo!(),
+//! Fingerprints of the build outputs, used to rebuild only what is stale.
+//!
+//! Next to each output (an object `foo.o` or a linked binary) we store a
+//! `foo.fp` file written after a successful build. It contains a hash of the
+//! full command line, a hash of the compiler version and a hash of every
+//! input: the source and the headers it includes (read from the `foo.d`
+//! depfile generated by gcc with `-MMD`), or the objects for a link.
+//!
+//! ```text
+//! command <sha256>
+//! compiler <sha256>
+//! input <sha256> <path>
+//! ```
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct Fingerprint {
+    /// Hash of the full command line used to build the output
+    pub command: String,
+    /// Hash of the compiler version
+    pub compiler: String,
+    /// Path and hash of each file read to build the output
+    pub inputs: Vec<(PathBuf, String)>,
+}
+
+/// Return the fingerprint path of the given build `output`
+pub fn fingerprint_path(output: &Path) -> PathBuf {
+    output.with_extension("fp")
+}
+
+/// Return the path where the `output` is written before being renamed into
+/// place, so an interrupted build never leaves a truncated output.
+pub fn temp_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".tmp");
+    PathBuf::from(path)
+}
+
+/// Return the depfile path that gcc writes for the given `object`
+pub fn depfile_path(object: &Path) -> PathBuf {
+    object.with_extension("d")
+}
+
+/// Hash of the content of a file, `None` if the file can't be read
+pub fn hash_file(path: &Path) -> Option<String> {
+    std::fs::read(path).ok().map(|c| sha256::digest_bytes(&c))
+}
+
+impl Fingerprint {
+    /// Compute the fingerprint of a build reading the given `inputs`.
+    pub fn new(command: &str, compiler: &str, inputs: Vec<PathBuf>) -> Result<Self> {
+        let inputs = inputs
+            .into_iter()
+            .map(|p| {
+                let hash = hash_file(&p)
+                    .ok_or_else(|| anyhow::anyhow!("unable to read {}", p.to_string_lossy()))?;
+                Ok((p, hash))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            command: sha256::digest(command),
+            compiler: sha256::digest(compiler),
+            inputs,
+        })
+    }
+
+    /// Load the fingerprint stored for `output`, `None` if there is no
+    /// fingerprint or if it's unreadable.
+    pub fn load(output: &Path) -> Option<Self> {
+        let content = std::fs::read_to_string(fingerprint_path(output)).ok()?;
+        let mut command = None;
+        let mut compiler = None;
+        let mut inputs = vec![];
+        for line in content.lines() {
+            let (key, value) = line.split_once(' ')?;
+            match key {
+                "command" => command = Some(value.to_string()),
+                "compiler" => compiler = Some(value.to_string()),
+                "input" => {
+                    let (hash, path) = value.split_once(' ')?;
+                    inputs.push((PathBuf::from(path), hash.to_string()));
+                }
+                _ => return None,
+            }
         }
+        Some(Self {
+            command: command?,
+            compiler: compiler?,
+            inputs,
+        })
+    }
+
+    /// Write the fingerprint next to the `output`.
+    pub fn save(&self, output: &Path) -> Result<()> {
+        let mut content = format!("command {}\ncompiler {}\n", self.command, self.compiler);
+        for (path, hash) in &self.inputs {
+            content.push_str(&format!("input {hash} {}\n", path.to_string_lossy()));
+        }
+        let path = fingerprint_path(output);
+        std::fs::write(temp_path(&path), content)?;
+        std::fs::rename(temp_path(&path), path)?;
+        Ok(())
     }
 }
 
-pub fn load_checksum_lock(dep_path: &Path) {}
+/// Check if `output` has to be rebuilt with the given `command` and
+/// `compiler` version. Return the reason of the rebuild, or `None` if the
+/// output is up to date.
+pub fn stale(output: &Path, command: &str, compiler: &str) -> Option<String> {
+    if !output.is_file() {
+        return Some(String::from("output is missing"));
+    }
+    let fingerprint = match Fingerprint::load(output) {
+        Some(fingerprint) => fingerprint,
+        None => return Some(String::from("no fingerprint")),
+    };
+    if fingerprint.command != sha256::digest(command) {
+        return Some(String::from("command line changed"));
+    }
+    if fingerprint.compiler != sha256::digest(compiler) {
+        return Some(String::from("compiler version changed"));
+    }
+    for (path, hash) in &fingerprint.inputs {
+        match hash_file(path) {
+            Some(h) if &h == hash => {}
+            Some(_) => return Some(format!("{} changed", path.to_string_lossy())),
+            None => return Some(format!("{} removed", path.to_string_lossy())),
+        }
+    }
+    None
+}
 
-*/
+/// Parse the content of a depfile generated by gcc (Makefile rule syntax)
+/// and return the prerequisites: the source followed by the headers.
+pub fn parse_depfile(content: &str) -> Vec<PathBuf> {
+    let content = content.replace("\\\r\n", " ").replace("\\\n", " ");
+    let rule = match content.lines().next() {
+        Some(rule) => rule,
+        None => return vec![],
+    };
+    let prerequisites = match rule.split_once(": ") {
+        Some((_, p)) => p,
+        None => return vec![],
+    };
+    let mut ret = vec![];
+    let mut current = String::new();
+    let mut chars = prerequisites.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => {
+                current.push(' ');
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    ret.push(PathBuf::from(std::mem::take(&mut current)));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        ret.push(PathBuf::from(current));
+    }
+    ret
+}
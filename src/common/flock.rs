@@ -0,0 +1,56 @@
+//! Advisory file locks on the cache, shared by all the `ch` processes using
+//! the same cache folder. A lock file is created for each locked resource in
+//! `${cache}/locks`, e.g. a layer clone, a source checkout or the build
+//! folder of a package.
+use crate::settings::Settings;
+use anyhow::{Context, Result};
+use colored::Colorize;
+use fs2::FileExt;
+use std::{
+    fs::{File, OpenOptions},
+    path::PathBuf,
+};
+use tracing::debug;
+
+/// Exclusive lock on a resource of the cache, released when dropped.
+pub struct CacheLock {
+    _file: File,
+}
+
+impl CacheLock {
+    /// Lock the resource `name`, wait if another process holds it. `what`
+    /// describes the resource to the user while waiting.
+    pub fn acquire(settings: &Settings, name: &str, what: &str) -> Result<Self> {
+        let (path, file) = open(settings, name)?;
+        if file.try_lock_exclusive().is_err() {
+            println!("{} for lock on {what}", "Waiting".yellow());
+            file.lock_exclusive()
+                .with_context(|| format!("unable to lock {}", path.to_string_lossy()))?;
+        }
+        debug!("locked {}", path.to_string_lossy());
+        Ok(Self { _file: file })
+    }
+
+    /// Lock the resource `name`, `None` if another process holds it.
+    pub fn try_acquire(settings: &Settings, name: &str) -> Result<Option<Self>> {
+        let (_, file) = open(settings, name)?;
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(Some(Self { _file: file })),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// Open the lock file of the resource `name`
+fn open(settings: &Settings, name: &str) -> Result<(PathBuf, File)> {
+    let mut path = settings.cache_dir.join("locks");
+    std::fs::create_dir_all(&path)?;
+    path.push(format!("{name}.lock"));
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("unable to open {}", path.to_string_lossy()))?;
+    Ok((path, file))
+}
@@ -0,0 +1,139 @@
+//! Resolved dependency graph of a package, with one node per package name
+//! and version.
+use crate::{
+    common::{
+        lockfile::LockFile,
+        resolver::Resolver,
+        types::{Dependency, Package},
+    },
+    settings::Settings,
+};
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use tracing::debug;
+
+/// Profile used to load the dependencies of a dependency. Only the release
+/// dependencies of a library are required to build it.
+const DEPENDENCY_PROFILE: &str = "release";
+
+pub struct Node {
+    pub dependency: Dependency,
+    pub package: Package,
+    /// Indexes of the direct dependencies in [DepGraph::nodes]
+    pub deps: Vec<usize>,
+}
+
+/// Dependencies of a root package. Nodes are stored in topological order,
+/// each node comes after all of its dependencies.
+pub struct DepGraph {
+    pub nodes: Vec<Node>,
+}
+
+/// Identify a node, (name, version)
+type Key = (String, String);
+
+fn key(package: &Package) -> Key {
+    (
+        package.pkg_description.name.clone(),
+        package.pkg_description.version.clone(),
+    )
+}
+
+impl DepGraph {
+    /// Load all the dependencies of the `root` package for the given
+    /// `profile`, with the versions chosen by the [Resolver]. The
+    /// versions of the `lock` are preferred when they match the requirements
+    /// and its commits are used for the git dependencies.
+    /// Return an error naming the path of the cycle if a package depends on
+    /// itself.
+    pub fn build(
+        root: &Package,
+        settings: &Settings,
+        profile: &str,
+        lock: Option<&LockFile>,
+    ) -> Result<Self> {
+        let mut resolver = Resolver::new(settings);
+        if let Some(lock) = lock {
+            resolver.pin(lock.pins());
+            resolver.pin_git(lock.git_pins());
+        }
+        loop {
+            resolver.start_pass()?;
+            let mut graph = DepGraph { nodes: vec![] };
+            let mut indexes = HashMap::new();
+            let mut path = vec![key(root)];
+            for dependency in sorted(root.profile(profile)?.get_dependencies()) {
+                graph.visit(dependency, &mut resolver, &mut indexes, &mut path)?;
+            }
+            if resolver.finish_pass()? {
+                return Ok(graph);
+            }
+        }
+    }
+
+    /// Depth first visit of a `dependency`, `path` is the list of packages
+    /// from the root to the dependent of the visited one.
+    fn visit(
+        &mut self,
+        dependency: Dependency,
+        resolver: &mut Resolver,
+        indexes: &mut HashMap<Key, usize>,
+        path: &mut Vec<Key>,
+    ) -> Result<usize> {
+        let (name, version) = path.last().unwrap();
+        let (dependency, package) = resolver.load(dependency, &format!("{name} {version}"))?;
+        let k = key(&package);
+        if let Some(start) = path.iter().position(|p| p == &k) {
+            let cycle = path[start..]
+                .iter()
+                .chain(std::iter::once(&k))
+                .map(|(name, version)| format!("{name} {version}"))
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            bail!("cyclic dependency: {cycle}")
+        }
+        if let Some(i) = indexes.get(&k) {
+            return Ok(*i);
+        }
+        debug!("add {} {} to the dependency graph", k.0, k.1);
+        path.push(k.clone());
+        let mut deps = vec![];
+        for d in sorted(package.profile(DEPENDENCY_PROFILE)?.get_dependencies()) {
+            deps.push(self.visit(d, resolver, indexes, path)?);
+        }
+        path.pop();
+        self.nodes.push(Node {
+            dependency,
+            package,
+            deps,
+        });
+        indexes.insert(k, self.nodes.len() - 1);
+        Ok(self.nodes.len() - 1)
+    }
+
+    /// Indexes of all the dependencies of the node `i`, direct or not, in
+    /// topological order.
+    pub fn transitive_deps(&self, i: usize) -> Vec<usize> {
+        let mut ret = vec![false; self.nodes.len()];
+        let mut stack = self.nodes[i].deps.clone();
+        while let Some(d) = stack.pop() {
+            if !ret[d] {
+                ret[d] = true;
+                stack.extend(&self.nodes[d].deps);
+            }
+        }
+        (0..self.nodes.len()).filter(|d| ret[*d]).collect()
+    }
+
+    /// Order in which the libraries have to be given to the linker, each
+    /// library before the libraries it depends on.
+    pub fn link_order(&self) -> impl Iterator<Item = usize> {
+        (0..self.nodes.len()).rev()
+    }
+}
+
+/// Sort dependencies by name, so the graph is the same at each build.
+fn sorted(mut dependencies: Vec<Dependency>) -> Vec<Dependency> {
+    dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+    dependencies
+}
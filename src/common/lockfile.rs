@@ -0,0 +1,253 @@
+//! `chataigne.lock`, written next to the package file, pins the resolved
+//! dependencies so every build of the same package file uses the same code.
+//!
+//! ```toml
+//! manifest = "<sha256 of chataigne.toml>"
+//!
+//! [[package]]
+//! name = "gtest"
+//! version = "1.11.0"
+//! source = "layer+https://github.com/adrien-zinger/layer_test_ch.git"
+//! layer_commit = "<commit of the layer>"
+//! src_commit = "<commit of the sources>"
+//! checksum = "<sha256 of the package file>"
+//! ```
+//!
+//! The lock is reused as long as the package file doesn't change.
+use crate::{
+    cmd::{git_head, pinned},
+    common::{
+        checksum::{hash_file, temp_path},
+        graph::Node,
+        tools::get_layer_directory,
+        types::{DepVal, Package, SrcVal},
+    },
+    settings::{Layer, Settings},
+};
+use anyhow::{bail, Context, Result};
+use semver::Version;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use tracing::debug;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    /// Where the package file comes from: `layer+<layer>`, `path+<path>`,
+    /// `git+<url>` or `system+<pkg-config module>`
+    pub source: String,
+    /// Commit of the layer if it's a git repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layer_commit: Option<String>,
+    /// Commit of the sources if they come from a git repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub src_commit: Option<String>,
+    /// Hash of the package file
+    pub checksum: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct LockFile {
+    /// Hash of the package file of the root package
+    pub manifest: String,
+    #[serde(default)]
+    pub package: Vec<LockedPackage>,
+}
+
+/// Path of the lock file of the `root` package
+pub fn lock_path(root: &Package) -> PathBuf {
+    root.file.with_file_name("chataigne.lock")
+}
+
+impl LockFile {
+    /// Load the lock of the `root` package, `None` if there is no lock or if
+    /// it was written for another version of the package file (`manifest`).
+    pub fn load(root: &Package, manifest: &str) -> Result<Option<Self>> {
+        let path = lock_path(root);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        let lock: Self = toml::from_str(&content)
+            .with_context(|| format!("unable to parse {}", path.to_string_lossy()))?;
+        if lock.manifest != manifest {
+            debug!("{} is outdated", path.to_string_lossy());
+            return Ok(None);
+        }
+        Ok(Some(lock))
+    }
+
+    /// Write the lock next to the package file of `root`. It's written in a
+    /// temporary file first, an interrupted save keeps the previous lock.
+    pub fn save(&self, root: &Package) -> Result<()> {
+        let content = format!(
+            "# Generated by chataigne, don't edit it by hand.\n{}",
+            toml::to_string(self)?
+        );
+        let path = lock_path(root);
+        std::fs::write(temp_path(&path), content)?;
+        std::fs::rename(temp_path(&path), path)?;
+        Ok(())
+    }
+
+    /// Locked package with the given name and version
+    pub fn get(&self, name: &str, version: &str) -> Option<&LockedPackage> {
+        self.package
+            .iter()
+            .find(|p| p.name == name && p.version == version)
+    }
+
+    /// Versions to try first in the resolution
+    pub fn pins(&self) -> HashMap<String, Version> {
+        self.package
+            .iter()
+            .filter_map(|p| Some((p.name.clone(), Version::parse(&p.version).ok()?)))
+            .collect()
+    }
+
+    /// Commits of the git dependencies, by url
+    pub fn git_pins(&self) -> HashMap<String, String> {
+        self.package
+            .iter()
+            .filter_map(|p| {
+                Some((
+                    p.source.strip_prefix("git+")?.to_string(),
+                    p.src_commit.clone()?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Settings with the git layers pinned at the commits of the lock. A
+    /// pinned layer has its own clone in the cache, the clone shared with
+    /// the builds without this lock isn't checked out.
+    pub fn pin_layers(&self, settings: &Settings) -> Settings {
+        let mut settings = settings.clone();
+        for layer in &mut settings.layers {
+            if let Layer::Git(git) = layer {
+                let source = format!("layer+{}", git.git);
+                let commit = self
+                    .package
+                    .iter()
+                    .find(|p| p.source == source)
+                    .and_then(|p| p.layer_commit.as_ref());
+                if let Some(commit) = commit {
+                    debug!("use layer {} at the locked commit {commit}", git.git);
+                    *git = pinned(git, commit);
+                }
+            }
+        }
+        settings
+    }
+
+    /// Use the locked source commits for the packages of the graph.
+    pub fn pin_sources(&self, nodes: &mut [Node]) {
+        for node in nodes {
+            let desc = &mut node.package.pkg_description;
+            let locked = match self.get(&desc.name, &desc.version) {
+                Some(locked) => locked,
+                None => continue,
+            };
+            if let (Some(SrcVal::Git(src)), Some(commit)) = (&mut desc.src, &locked.src_commit) {
+                *src = pinned(src, commit);
+            }
+        }
+    }
+
+    /// Create the new lock of a build that used the `locked` packages. The
+    /// packages of the `previous` lock not used by this build (other
+    /// profiles) are kept if the package file didn't change.
+    pub fn new(manifest: String, locked: Vec<LockedPackage>, previous: Option<&Self>) -> Self {
+        let mut package = locked;
+        if let Some(previous) = previous.filter(|p| p.manifest == manifest) {
+            for p in &previous.package {
+                if !package.iter().any(|l| l.name == p.name) {
+                    package.push(p.clone());
+                }
+            }
+        }
+        package.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        package.dedup();
+        Self { manifest, package }
+    }
+}
+
+/// Write the lock of the `root` package if the `locked` packages of the
+/// build changed it. Fail instead if `frozen` (`--locked`) is set.
+pub fn update(
+    root: &Package,
+    manifest: String,
+    locked: Vec<LockedPackage>,
+    previous: Option<LockFile>,
+    frozen: bool,
+) -> Result<()> {
+    let lock = LockFile::new(manifest, locked, previous.as_ref());
+    if previous.as_ref() == Some(&lock) {
+        return Ok(());
+    }
+    if frozen {
+        bail!(
+            "{} needs to be updated but --locked was given",
+            lock_path(root).to_string_lossy()
+        )
+    }
+    lock.save(root)
+}
+
+/// Hash of the package file of the root package
+pub fn manifest_hash(root: &Package) -> Result<String> {
+    hash_file(&root.file).with_context(|| format!("unable to read {}", root.file.to_string_lossy()))
+}
+
+/// Describe a resolved package of the graph, `src_path` is the folder of its
+/// sources after the checkout.
+pub fn locked_package(node: &Node, src_path: &Path, settings: &Settings) -> Result<LockedPackage> {
+    let desc = &node.package.pkg_description;
+    let (source, layer_commit) = match &node.dependency.desc {
+        DepVal::Version(_) => layer_source(&node.package, settings)?,
+        DepVal::Path(path) => (format!("path+{}", path.to_string_lossy()), None),
+        DepVal::Git(git) => (format!("git+{}", git.git), None),
+        DepVal::System(system) => (format!("system+{}", system.system), None),
+    };
+    let src_commit = match (&node.dependency.desc, &desc.src) {
+        (DepVal::Git(_), _) | (_, Some(SrcVal::Git(_))) => Some(git_head(src_path)?),
+        _ => None,
+    };
+    let checksum = match (&node.dependency.desc, hash_file(&node.package.file)) {
+        // no package file, the flags given by pkg-config describe it
+        (DepVal::System(_), _) => {
+            let flags = &node.package.get_lib()?.flags;
+            sha256::digest(format!("{:?} {:?}", flags.cflags, flags.libs))
+        }
+        (_, Some(checksum)) => checksum,
+        (_, None) => bail!("unable to read {}", node.package.file.to_string_lossy()),
+    };
+    Ok(LockedPackage {
+        name: desc.name.clone(),
+        version: desc.version.clone(),
+        source,
+        layer_commit,
+        src_commit,
+        checksum,
+    })
+}
+
+/// Find the layer containing the package file of `package`, return its
+/// source and its commit if it's a git repository.
+fn layer_source(package: &Package, settings: &Settings) -> Result<(String, Option<String>)> {
+    for layer in &settings.layers {
+        let path = get_layer_directory(layer, settings)?;
+        if !package.file.starts_with(&path) {
+            continue;
+        }
+        return Ok(match layer {
+            Layer::Git(git) => (format!("layer+{}", git.git), Some(git_head(&path)?)),
+            Layer::Dir(dir) => (format!("layer+{}", dir.path), None),
+        });
+    }
+    bail!("{} isn't in a layer", package.file.to_string_lossy())
+}
@@ -1,3 +1,7 @@
 pub mod checksum;
+pub mod flock;
+pub mod graph;
+pub mod lockfile;
+pub mod resolver;
 pub mod tools;
 pub mod types;
@@ -0,0 +1,231 @@
+//! Choose one version of each layer package that satisfies the requirements
+//! of all its dependents.
+//!
+//! The dependency graph is loaded in passes. During a pass, the first time a
+//! package is required, we take the version selected by the previous pass or
+//! the highest version matching that first requirement, and we collect all
+//! the requirements. At the end of the pass, we select for each package the
+//! highest version matching all its requirements. The resolution ends when a
+//! pass loaded exactly the selected versions. A selected version, e.g. from
+//! the lock file, is kept as long as it matches all the requirements.
+use crate::{
+    cmd::pinned,
+    common::{
+        tools::{find_pkg, layer_versions, parse_requirement, LayerPkg},
+        types::{DepVal, Dependency, Package},
+    },
+    settings::Settings,
+};
+use anyhow::{bail, Result};
+use semver::{Version, VersionReq};
+use std::collections::HashMap;
+use tracing::debug;
+
+/// Maximum number of passes before giving up the resolution
+const MAX_PASSES: usize = 16;
+
+/// Version requirement of a package on a dependency
+pub struct Requirement {
+    /// Name and version of the package requiring the dependency
+    pub by: String,
+    /// Requirement as written in the package file
+    pub raw: String,
+    pub req: VersionReq,
+}
+
+pub struct Resolver<'a> {
+    settings: &'a Settings,
+    /// Versions available in the layers, by package name
+    available: HashMap<String, Vec<LayerPkg>>,
+    /// Versions selected at the end of the previous pass
+    selected: HashMap<String, Version>,
+    /// Versions of the packages given by a path or a git repository. They
+    /// can't be changed, requirements are just checked against them.
+    fixed: HashMap<String, (Version, DepVal)>,
+    /// Commits to checkout for the git dependencies, by url
+    git_pins: HashMap<String, String>,
+    /// Versions loaded during the current pass
+    loaded: HashMap<String, Version>,
+    /// Requirements collected during the current pass
+    requirements: HashMap<String, Vec<Requirement>>,
+    passes: usize,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(settings: &'a Settings) -> Self {
+        Self {
+            settings,
+            available: HashMap::new(),
+            selected: HashMap::new(),
+            fixed: HashMap::new(),
+            git_pins: HashMap::new(),
+            loaded: HashMap::new(),
+            requirements: HashMap::new(),
+            passes: 0,
+        }
+    }
+
+    /// Try first the given versions, e.g. the versions of the lock file.
+    pub fn pin(&mut self, pins: HashMap<String, Version>) {
+        self.selected.extend(pins);
+    }
+
+    /// Checkout the given commits of the git dependencies instead of the
+    /// commit, tag or branch written in the package files.
+    pub fn pin_git(&mut self, pins: HashMap<String, String>) {
+        self.git_pins.extend(pins);
+    }
+
+    /// Start a new pass, forget what has been loaded in the previous one.
+    pub fn start_pass(&mut self) -> Result<()> {
+        if self.passes == MAX_PASSES {
+            bail!("unable to resolve the versions of the dependencies in {MAX_PASSES} passes")
+        }
+        self.passes += 1;
+        self.loaded.clear();
+        self.requirements.clear();
+        Ok(())
+    }
+
+    /// Load the package file of the `dependency`, required by the package
+    /// `by` (name and version). Return the dependency really used, a version
+    /// requirement on a package given by a path or a git repository is
+    /// replaced by that one.
+    pub fn load(&mut self, mut dependency: Dependency, by: &str) -> Result<(Dependency, Package)> {
+        if let DepVal::Git(git) = &mut dependency.desc {
+            if let Some(commit) = self.git_pins.get(&git.git) {
+                *git = pinned(git, commit);
+            }
+        }
+        let name = dependency.name.clone();
+        let raw = match &dependency.desc {
+            DepVal::Version(raw) => raw.clone(),
+            _ => {
+                let package = find_pkg(&dependency, self.settings)?;
+                if let Ok(version) = Version::parse(&package.pkg_description.version) {
+                    self.fixed.insert(name, (version, dependency.desc.clone()));
+                }
+                return Ok((dependency, package));
+            }
+        };
+        let req = parse_requirement(&name, &raw)?;
+        self.requirements
+            .entry(name.clone())
+            .or_default()
+            .push(Requirement {
+                by: by.to_string(),
+                raw: raw.clone(),
+                req: req.clone(),
+            });
+        if let Some((_, desc)) = self.fixed.get(&name) {
+            let dependency = Dependency {
+                name,
+                desc: desc.clone(),
+            };
+            let package = find_pkg(&dependency, self.settings)?;
+            return Ok((dependency, package));
+        }
+        let previous = self
+            .loaded
+            .get(&name)
+            .or_else(|| self.selected.get(&name))
+            .cloned();
+        let versions = self.versions(&name)?;
+        let version = match previous.filter(|v| versions.iter().any(|p| &p.version == v)) {
+            Some(version) => version,
+            None => match versions.iter().find(|p| req.matches(&p.version)) {
+                Some(p) => p.version.clone(),
+                None => bail!(
+                    "no version of {name} matches {raw:?} required by {by}\n{}",
+                    self.available_versions(&name)
+                ),
+            },
+        };
+        debug!("load {name} {version}");
+        self.loaded.insert(name.clone(), version.clone());
+        let package = self.read(&name, &version)?;
+        Ok((dependency, package))
+    }
+
+    /// End the current pass, select the versions matching all the collected
+    /// requirements. Return true if the loaded versions were the selected
+    /// ones, otherwise a new pass is required.
+    pub fn finish_pass(&mut self) -> Result<bool> {
+        let mut stable = true;
+        let mut names: Vec<&String> = self.requirements.keys().collect();
+        names.sort();
+        for name in names {
+            let reqs = &self.requirements[name];
+            if let Some((version, _)) = self.fixed.get(name) {
+                if reqs.iter().any(|r| !r.req.matches(version)) {
+                    let details =
+                        format!("{name} {version} is given by a path or a git repository");
+                    bail!(self.conflict(name, &details))
+                }
+                // a layer version has been loaded before we knew it was fixed
+                stable &= !self.loaded.contains_key(name);
+                continue;
+            }
+            // keep the selected version if it still matches, so the pinned
+            // versions aren't upgraded
+            let mut matching = self.available[name]
+                .iter()
+                .filter(|p| reqs.iter().all(|r| r.req.matches(&p.version)));
+            let best = matching
+                .clone()
+                .find(|p| Some(&p.version) == self.selected.get(name))
+                .or_else(|| matching.next());
+            let best = match best {
+                Some(p) => p.version.clone(),
+                None => bail!(self.conflict(name, &self.available_versions(name))),
+            };
+            if self.loaded.get(name) != Some(&best) {
+                debug!("select {name} {best} for the next pass");
+                stable = false;
+            }
+            self.selected.insert(name.clone(), best);
+        }
+        Ok(stable)
+    }
+
+    /// Versions of `name` available in the layers, memoized
+    fn versions(&mut self, name: &str) -> Result<&Vec<LayerPkg>> {
+        if !self.available.contains_key(name) {
+            let versions = layer_versions(name, self.settings)?;
+            self.available.insert(name.to_string(), versions);
+        }
+        Ok(&self.available[name])
+    }
+
+    fn read(&mut self, name: &str, version: &Version) -> Result<Package> {
+        match self.versions(name)?.iter().find(|p| &p.version == version) {
+            Some(p) => Package::read(Some(p.path.to_string_lossy().to_string())),
+            None => bail!("{name} {version} not found in the layers"),
+        }
+    }
+
+    fn available_versions(&self, name: &str) -> String {
+        match self.available.get(name) {
+            Some(versions) if !versions.is_empty() => format!(
+                "available versions: {}",
+                versions
+                    .iter()
+                    .map(|p| p.version.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            _ => format!("{name} isn't in any layer"),
+        }
+    }
+
+    /// Explain why the requirements of `name` can't be satisfied
+    fn conflict(&self, name: &str, details: &str) -> String {
+        let mut ret = format!("unable to find a version of {name} matching all the requirements:");
+        for r in &self.requirements[name] {
+            ret.push_str(&format!("\n  {} requires {} {}", r.by, name, r.raw));
+        }
+        ret.push('\n');
+        ret.push_str(details);
+        ret
+    }
+}
@@ -1,13 +1,18 @@
 use crate::{
-    cmd::git_clone,
-    common::types::{DepVal, Dependency, Package},
+    cmd::{cache::touch, checkout_git_dependency, git_fetch, system_package, Revision},
+    common::{
+        flock::CacheLock,
+        types::{DepVal, Dependency, Package},
+    },
     settings::{self, Layer, Settings},
 };
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use glob::Pattern;
+use semver::{Version, VersionReq};
 use std::{
-    collections::HashSet,
+    collections::BTreeSet,
     path::{Path, PathBuf},
+    time::Duration,
 };
 use tracing::debug;
 use walkdir::WalkDir;
@@ -23,13 +28,33 @@ pub fn concat(root: &Path, file: &str) -> String {
     ret.to_str().unwrap().to_string()
 }
 
-/// Find layer directory, in case of a git repository, clone
+/// Find layer directory, in case of a git repository, clone it in
+/// `${cache}/layers` if it's missing. An existing clone is left as is.
+///
+/// The clone of a layer is in `${cache}/layers/${sha256 of the url}`, a
+/// layer at a given revision, e.g. pinned by a lock, has its own clone
+/// suffixed by a key of the revision. A clone is never checked out again,
+/// so the builds of other projects don't change the layers of each other.
 pub fn get_layer_directory(layer: &Layer, settings: &Settings) -> Result<PathBuf> {
     match layer {
         settings::Layer::Git(git) => {
-            let mut p = settings.project_dirs.cache_dir().to_path_buf();
-            p.push(sha256::digest(&git.git));
-            git_clone(&git.git, &None, &p);
+            let name = match Revision::of(git)? {
+                Revision::Head => sha256::digest(&git.git),
+                revision => format!(
+                    "{}_{}",
+                    sha256::digest(&git.git),
+                    &sha256::digest(format!("{}#{revision}", git.git))[..16]
+                ),
+            };
+            let mut p = settings.cache_dir.join("layers");
+            p.push(&name);
+            if !p.is_dir() {
+                let _lock = CacheLock::acquire(settings, &format!("layer_{name}"), &git.git)?;
+                if !p.is_dir() {
+                    git_fetch(git, &p)?;
+                }
+            }
+            touch(&p);
             Ok(p)
         }
         settings::Layer::Dir(dir) => {
@@ -42,9 +67,110 @@ pub fn get_layer_directory(layer: &Layer, settings: &Settings) -> Result<PathBuf
     }
 }
 
-pub fn unwrap_path_patterns(path: &Path, patterns: &[String]) -> HashSet<PathBuf> {
+/// A version of a package available in a layer
+pub struct LayerPkg {
+    pub version: Version,
+    /// Path of the package file `${layer}/${name}/${version}.toml`
+    pub path: PathBuf,
+}
+
+/// List the versions of the package `name` available in all the layers, from
+/// the highest to the lowest. If several layers contain the same version, the
+/// first layer in the settings wins. Files that aren't named with a semantic
+/// version are ignored.
+pub fn layer_versions(name: &str, settings: &Settings) -> Result<Vec<LayerPkg>> {
+    let mut ret: Vec<LayerPkg> = vec![];
+    for layer in &settings.layers {
+        let mut dir = get_layer_directory(layer, settings)?;
+        dir.push(name);
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.extension().is_none_or(|e| e != "toml") {
+                continue;
+            }
+            let stem = path.file_stem().unwrap().to_string_lossy();
+            match Version::parse(&stem) {
+                Ok(version) if !ret.iter().any(|p| p.version == version) => {
+                    ret.push(LayerPkg { version, path })
+                }
+                Ok(_) => {}
+                Err(_) => debug!("ignore {}, not a version", path.to_string_lossy()),
+            }
+        }
+    }
+    ret.sort_by(|a, b| b.version.cmp(&a.version));
+    Ok(ret)
+}
+
+/// Parse the version requirement `req` of the dependency `name`, e.g.
+/// `1.11.0`, `^1.11`, `~1.2.3`, `>=1.2, <2.0` or `*`. A bare version is a
+/// caret requirement, as in cargo.
+pub fn parse_requirement(name: &str, req: &str) -> Result<VersionReq> {
+    VersionReq::parse(req)
+        .with_context(|| format!("invalid version requirement {req:?} for {name}"))
+}
+
+/// Parse a size in bytes with an optional `K`, `M`, `G` or `T` suffix (powers
+/// of 1024), e.g. `500M` or `10GiB`.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let s = size.trim().trim_end_matches('B').trim_end_matches('i');
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let unit: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => bail!("invalid size {size:?}, expected e.g. 500M or 10G"),
+    };
+    match number.parse::<u64>() {
+        Ok(n) => Ok(n * unit),
+        Err(_) => bail!("invalid size {size:?}, expected e.g. 500M or 10G"),
+    }
+}
+
+/// Human readable size, e.g. `1.5 MiB`
+pub fn format_size(size: u64) -> String {
+    let mut size = size as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return match unit {
+                "B" => format!("{size} {unit}"),
+                _ => format!("{size:.1} {unit}"),
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.1} TiB")
+}
+
+/// Parse a duration with a `s`, `m`, `h`, `d` or `w` suffix, e.g. `30d`.
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let s = duration.trim();
+    let secs: u64 = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        Some('w') => 7 * 86400,
+        _ => bail!("invalid duration {duration:?}, expected e.g. 12h or 30d"),
+    };
+    match s[..s.len() - 1].parse::<u64>() {
+        Ok(n) => Ok(Duration::from_secs(n * secs)),
+        Err(_) => bail!("invalid duration {duration:?}, expected e.g. 12h or 30d"),
+    }
+}
+
+pub fn unwrap_path_patterns(path: &Path, patterns: &[String]) -> BTreeSet<PathBuf> {
     debug!("unwrap patterns {:?}", patterns);
-    let mut ret = HashSet::new();
+    let mut ret = BTreeSet::new();
     for entry in WalkDir::new(path)
         .follow_links(true)
         .into_iter()
@@ -68,50 +194,57 @@ pub fn unwrap_path_patterns(path: &Path, patterns: &[String]) -> HashSet<PathBuf
 }
 
 // todo: return better errors, readable by final user
-// todo, clone git layers
-// todo, clone git dependency
 
-/// Return the pkg file of the dependency. Done for each dependencies in
-/// the full project including [sub]+ dependencies.
+/// Return the pkg file of the dependency. A version requirement is resolved
+/// to the highest matching version in the layers, the [Resolver] also takes
+/// into account the requirements of the other packages.
 ///
-/// The search of the sources of the dependencies can be done here if the
-/// dependency value is a git target. Otherwise, we just check in the layer or
-/// in the local if toml exist.
+/// A git dependency is cloned here, its repository contains its package file
+/// as a path dependency. Otherwise, we just check in the layer or in the
+/// local if toml exist.
 ///
 /// In cases where we don't clone the sources. It's done later just before
 /// running the compilation of each lib. It's done in [compile_lib] with the
 /// usefull function [checkout].
+///
+/// [Resolver]: crate::common::resolver::Resolver
 pub fn find_pkg(dependency: &Dependency, settings: &Settings) -> Result<Package> {
     debug!("Load dependency {} pkgfile", dependency.name);
     match &dependency.desc {
-        DepVal::Version(version) => {
-            for layer in &settings.layers {
-                // todo, by default check filesystem for layers, but if
-                // git repository, clone the layer before
-                let mut dir = get_layer_directory(layer, settings)?;
-                dir.push(&dependency.name);
-                dir.push(format!("{version}.toml"));
-                if dir.is_file() {
-                    return Package::read(Some(dir.to_str().unwrap().to_string()));
-                }
+        DepVal::Version(req) => {
+            let req = parse_requirement(&dependency.name, req)?;
+            let versions = layer_versions(&dependency.name, settings)?;
+            if let Some(pkg) = versions.iter().find(|p| req.matches(&p.version)) {
+                return Package::read(Some(pkg.path.to_string_lossy().to_string()));
             }
         }
-        DepVal::Path(path) => {
-            let pkg_file_path = {
-                let mut p = path.clone();
-                p.push(DEFAULT_PACKAGE_FILE_NAME);
-                p
-            };
-            debug!(
-                "Find pkg {} in path {}",
-                &dependency.name,
-                pkg_file_path.to_string_lossy()
-            );
-            if pkg_file_path.is_file() {
-                return Package::read(Some(pkg_file_path.to_str().unwrap().to_string()));
-            }
+        DepVal::Path(path) => return read_path_pkg(&dependency.name, path),
+        DepVal::Git(git) => {
+            let path = checkout_git_dependency(&dependency.name, git, settings)?;
+            return read_path_pkg(&dependency.name, &path);
         }
-        DepVal::Git(_) => todo!("will be implemented soon"),
+        DepVal::System(system) => return system_package(&dependency.name, system),
     }
     bail!("unable to read dependency")
 }
+
+/// Read the package file of a dependency whose sources are in `path`, a
+/// path or a git dependency.
+fn read_path_pkg(name: &str, path: &Path) -> Result<Package> {
+    let pkg_file_path = {
+        let mut p = path.to_path_buf();
+        p.push(DEFAULT_PACKAGE_FILE_NAME);
+        p
+    };
+    debug!(
+        "Find pkg {name} in path {}",
+        pkg_file_path.to_string_lossy()
+    );
+    if !pkg_file_path.is_file() {
+        bail!(
+            "unable to read dependency {name}, {} not found",
+            pkg_file_path.to_string_lossy()
+        )
+    }
+    Package::read(Some(pkg_file_path.to_str().unwrap().to_string()))
+}
@@ -1,44 +1,136 @@
 use config::Value;
 use serde_derive::Deserialize;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap},
     path::PathBuf,
 };
 
-/// Parse the build options in package files. Will be adapted into
-/// [BuildOption], don't use that structure outside deserialization.
+/// Parse the build options of a profile in package files, in a
+/// `[profile.<name>]` section or in the legacy `[dev]` and `[test]` ones.
+/// Will be adapted into [BuildOption], don't use that structure outside
+/// deserialization.
 #[derive(Deserialize, Default)]
 pub struct ConfigBuildOption {
+    /// Profile to inherit from
+    pub inherits: Option<String>,
+    /// Optimization level, `-O<opt-level>`
+    #[serde(rename = "opt-level")]
+    pub opt_level: Option<String>,
+    /// Generate debug information, `-g`
+    pub debug: Option<bool>,
+    /// Compiler and linker flags added by the profile
+    #[serde(flatten)]
+    pub flags: Flags,
     /// Paths that will be ignored
-    #[serde(default)]
-    pub ignore: Vec<String>,
+    pub ignore: Option<Vec<String>>,
     pub dependencies: Option<HashMap<String, Value>>,
     /// Overriding the auto sources loader
-    #[serde(default)]
-    pub sources: Vec<String>,
+    pub sources: Option<Vec<String>>,
     /// Overriding the auto includes loader
-    #[serde(default)]
-    pub includes: Vec<String>,
-    /// Simply add options (flags) to the build
-    #[serde(default)]
-    pub opt: Vec<String>,
+    pub includes: Option<Vec<String>>,
 }
 
 /// Same as [ConfigBuildOption] but after a little adaptation to be used in rust
-/// code.
+/// code. Options that are `None` are inherited.
+#[derive(Default, Clone)]
 pub struct BuildOption {
-    /// Paths that will be ignored, default change for test, and dev/release
-    pub ignore: Vec<String>,
+    pub inherits: Option<String>,
+    pub opt_level: Option<String>,
+    pub debug: Option<bool>,
+    pub flags: Flags,
+    /// Paths that will be ignored
+    pub ignore: Option<Vec<String>>,
     /// Build dependencies
-    pub dependencies: Option<HashMap<String, DepVal>>,
+    pub dependencies: HashMap<String, DepVal>,
     /// Overriding auto detection of build sources (path and regex)
-    pub sources: Vec<String>,
+    pub sources: Option<Vec<String>>,
     /// Overriding auto detection of includes/headers (path and regex)
+    pub includes: Option<Vec<String>>,
+}
+
+/// Build options of a profile after the inheritance, see [Package::profile]
+pub struct Profile {
+    pub name: String,
+    pub opt_level: String,
+    pub debug: bool,
+    pub flags: Flags,
+    /// Flags of the profile sections only, also given to the compilation of
+    /// the dependencies
+    pub options: Flags,
+    pub ignore: Vec<String>,
+    pub dependencies: HashMap<String, DepVal>,
+    pub sources: Vec<String>,
     pub includes: Vec<String>,
-    /// Simply add options (flags) to the build
+}
+
+/// Where a flag is given to gcc
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Compilation of the package's own sources
+    Private,
+    /// Compilation of the package and of all the packages depending on it
+    Public,
+    /// Link of the executable only
+    Link,
+}
+
+/// A flag in a package file, either a plain string using the default scope
+/// of its list or a table.
+///
+/// ```toml
+/// cxxflags=["-Wall", {value="-std=c++17", scope="public"}]
+/// ```
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum ConfigFlag {
+    Value(String),
+    Scoped { value: String, scope: Option<Scope> },
+}
+
+/// Flag lists of a package file, the top level, a `[profile.<name>]` or the
+/// `[lib]`. Will be adapted into [Flags].
+#[derive(Deserialize, Default)]
+pub struct ConfigFlags {
+    #[serde(default)]
+    pub cflags: Vec<ConfigFlag>,
+    #[serde(default)]
+    pub cxxflags: Vec<ConfigFlag>,
+    #[serde(default)]
+    pub defines: Vec<ConfigFlag>,
+    #[serde(default)]
+    pub ldflags: Vec<ConfigFlag>,
+    #[serde(default)]
+    pub libs: Vec<ConfigFlag>,
+    /// Legacy flags given to both the compilation of the package and the
+    /// link
+    #[serde(default)]
     pub opt: Vec<String>,
 }
 
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Flag {
+    pub value: String,
+    pub scope: Scope,
+}
+
+/// Compiler and linker flags of a package, see [Flags::compile_args] and
+/// [Flags::link_args] for where each list goes.
+#[derive(Deserialize, Default, Clone)]
+#[serde(try_from = "ConfigFlags")]
+pub struct Flags {
+    /// Flags of the compilation of every source, `private` by default
+    pub cflags: Vec<Flag>,
+    /// Flags of the compilation of the C++ sources, `private` by default
+    pub cxxflags: Vec<Flag>,
+    /// Preprocessor definitions, `-D<define>`, `private` by default
+    pub defines: Vec<Flag>,
+    /// Flags of the link
+    pub ldflags: Vec<String>,
+    /// Libraries to link with, `-l<lib>` unless it's a path or a flag
+    pub libs: Vec<String>,
+}
+
 /// Package deduced for a toml file. It will be changed into a [Package]
 /// for a better usage in the code.
 #[derive(Deserialize)]
@@ -46,15 +138,16 @@ pub struct ConfigPackage {
     pub package: ConfigPkgDescription,
     #[serde(default)]
     pub ignore: Vec<String>,
+    #[serde(flatten)]
+    pub flags: Flags,
+    /// Legacy `[dev]` section, same as `[profile.dev]`
+    pub dev: Option<ConfigBuildOption>,
+    /// Legacy `[test]` section, same as `[profile.test]`
+    pub test: Option<ConfigBuildOption>,
     #[serde(default)]
-    pub opt: Vec<String>,
-    #[serde(default)]
-    pub dev: ConfigBuildOption,
-    #[serde(default)]
-    pub test: ConfigBuildOption,
+    pub profile: HashMap<String, ConfigBuildOption>,
 
     pub dependencies: Option<HashMap<String, Value>>,
-    // todo: we can also have a shared library or dynamic.
     pub lib: Option<StaticLib>,
 }
 
@@ -65,6 +158,7 @@ pub struct ConfigPkgDescription {
     pub name: String,
     pub version: String,
     pub description: Option<String>,
+    #[allow(dead_code)]
     pub authors: Option<Vec<String>>,
     pub src: Option<Value>,
     pub repostory: Option<String>,
@@ -74,19 +168,127 @@ pub struct ConfigPkgDescription {
     pub includes: Vec<String>,
 }
 
+/// How a library is built and linked
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LibKind {
+    /// Archive `lib<name>.a` linked into the executable
+    #[default]
+    Static,
+    /// Shared object `lib<name>.so` loaded when the executable starts
+    Shared,
+    /// Only headers, nothing is compiled. All its flags are given to the
+    /// packages using it.
+    #[serde(rename = "header-only")]
+    HeaderOnly,
+    /// Installed on the system and found with `pkg-config`, only used for
+    /// the `system` dependencies
+    #[serde(skip)]
+    System,
+}
+
 #[derive(Deserialize)]
 pub struct StaticLib {
+    #[serde(default)]
+    pub kind: LibKind,
+    /// Library shipped already built, nothing is compiled
+    pub prebuilt: Option<Prebuilt>,
+    /// Build system of the library, its sources are compiled by chataigne
+    /// otherwise
+    pub build: Option<BuildSystem>,
+    /// Options of a `cmake` build
+    #[serde(default)]
+    pub cmake: CMakeOptions,
+    /// Options of a `make` or `autotools` build
+    #[serde(default)]
+    pub make: MakeOptions,
     #[serde(default)]
     pub headers: Vec<String>,
     // todo make `builds` deprecated and use `sources` instead
     #[serde(default)]
     pub builds: Vec<String>,
+    /// Flags of the library, the `public` ones are also given to the
+    /// packages using it
+    #[serde(flatten)]
+    pub flags: Flags,
     #[serde(default)]
-    pub opt: Vec<String>,
-    #[serde(default)]
+    #[allow(dead_code)]
     pub ignore: Vec<String>,
 }
 
+/// Build system of a library with its own build files
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildSystem {
+    /// `CMakeLists.txt`, see [CMakeOptions]
+    CMake,
+    /// `Makefile` without installation, see [MakeOptions]
+    Make,
+    /// `./configure && make && make install`, see [MakeOptions]
+    Autotools,
+}
+
+/// Options of a library built with `cmake`.
+///
+/// ```toml
+/// [lib]
+/// build = "cmake"
+/// cmake = { variables = { ABSL_PROPAGATE_CXX_STD = "ON" }, targets = ["absl_strings"] }
+/// ```
+#[derive(Deserialize, Clone, Default)]
+pub struct CMakeOptions {
+    /// Cache variables given to the configuration, `-D<name>=<value>`
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    /// Targets to build, all of them by default
+    #[serde(default)]
+    pub targets: Vec<String>,
+}
+
+/// Options of a library built with `make` or `autotools`.
+///
+/// ```toml
+/// [lib]
+/// build = "autotools"
+/// make = { configure = ["--disable-nls"], targets = ["all"] }
+/// ```
+#[derive(Deserialize, Clone, Default)]
+pub struct MakeOptions {
+    /// Arguments of `./configure`, only for `autotools`
+    #[serde(default)]
+    pub configure: Vec<String>,
+    /// Targets given to `make`, the default one otherwise
+    #[serde(default)]
+    pub targets: Vec<String>,
+}
+
+/// Library shipped already built, e.g. a vendor SDK. Paths are relative to
+/// the sources of the package.
+///
+/// ```toml
+/// [lib]
+/// prebuilt = { static = "lib/libfoo.a", shared = "lib/libfoo.so", headers = ["include"] }
+///
+/// # or by architecture, the entry of the current one overrides the others
+/// [lib.prebuilt]
+/// headers = ["include"]
+/// arch.x86_64 = { static = "lib/x86_64/libfoo.a", sha256 = "..." }
+/// arch.aarch64 = { static = "lib/aarch64/libfoo.a", sha256 = "..." }
+/// ```
+#[derive(Deserialize, Clone, Default)]
+pub struct Prebuilt {
+    #[serde(rename = "static")]
+    pub static_lib: Option<String>,
+    pub shared: Option<String>,
+    /// Folders to include, the `headers` of the `[lib]` otherwise
+    pub headers: Option<Vec<String>>,
+    /// Expected sha256 of the library that is linked
+    pub sha256: Option<String>,
+    /// Entries by architecture, named as `std::env::consts::ARCH`
+    #[serde(default)]
+    pub arch: HashMap<String, Prebuilt>,
+}
+
 #[derive(Deserialize, Clone)]
 /// Dependency parsed in package files. Simplifyed into that
 /// enum.
@@ -94,12 +296,47 @@ pub enum DepVal {
     Version(String),
     Path(PathBuf),
     Git(GitTarget),
+    System(SystemTarget),
+}
+
+/// Library installed on the system, found with `pkg-config`.
+///
+/// ```toml
+/// [dependencies]
+/// openssl = { system = "openssl", version = ">=1.1" }
+/// ```
+#[derive(Deserialize, Clone)]
+pub struct SystemTarget {
+    /// Name of the `pkg-config` module
+    pub system: String,
+    /// Requirements on the installed version, compared by `pkg-config`
+    pub version: Option<String>,
 }
 
 #[derive(Deserialize, Clone)]
 pub enum SrcVal {
     Local(LocalTarget),
     Git(GitTarget),
+    Archive(ArchiveTarget),
+}
+
+/// Sources of a layer package published as an archive, `.tar.*` or `.zip`.
+///
+/// ```toml
+/// [package]
+/// src = { url = "https://example.com/foo-1.2.tar.gz", sha256 = "...", strip_prefix = "foo-1.2" }
+/// ```
+#[derive(Deserialize, Clone)]
+pub struct ArchiveTarget {
+    /// `https://`, `file://` url or path relative to the package file
+    pub url: String,
+    /// Hash of the archive, checked before extracting it
+    pub sha256: String,
+    /// Folder of the archive containing the sources, its root otherwise
+    pub strip_prefix: Option<String>,
+    /// Patch files applied after the extraction, relative to the package file
+    #[serde(default)]
+    pub patches: Vec<PathBuf>,
 }
 
 #[derive(Deserialize, Clone)]
@@ -118,15 +355,33 @@ pub struct LocalTarget {
 /// # in a dependency description
 /// [dependencies]
 /// sample={git = "...", commit="..."}
+/// other={git = "...", tag="v1.2", shallow=true, submodules=true}
 ///
 /// # as a layer in settings.toml
 /// layers=["local_path", {git = "...", commit="..."}]
 /// ```
-#[derive(Deserialize, Clone)]
+///
+/// At most one of `commit`, `rev`, `tag` and `branch` can be given, the
+/// default branch of the repository is used otherwise.
+#[derive(Deserialize, Clone, Default)]
 pub struct GitTarget {
     /// Git url
     pub git: String,
+    /// Commit, tag or branch
     pub commit: Option<String>,
+    /// Commit hash
+    pub rev: Option<String>,
+    pub tag: Option<String>,
+    pub branch: Option<String>,
+    /// Only fetch the history required by the checkout
+    #[serde(default)]
+    pub shallow: bool,
+    /// Initialize the submodules of the repository
+    #[serde(default)]
+    pub submodules: bool,
+    /// Patch files applied after the checkout, relative to the package file
+    #[serde(default)]
+    pub patches: Vec<PathBuf>,
 }
 
 /// Structured representation of dependencies in PkgFile,
@@ -144,23 +399,26 @@ pub struct PkgDescription {
     /// Information about the version of the package
     pub version: String,
     /// Description pure text of the package
+    #[allow(dead_code)]
     pub description: Option<String>,
     /// Define in a package description (for dependencies) where the user can find
     /// the repository where sources are stored
     pub src: Option<SrcVal>,
     /// Information about the repository. Nothing very usefull
+    #[allow(dead_code)]
     pub repostory: Option<String>,
 }
 
 pub struct Package {
+    /// Path of the package file
+    pub file: PathBuf,
     pub pkg_description: PkgDescription,
     pub dependencies: Option<HashMap<String, DepVal>>,
-    /// Simply add options (flags) to the build
-    pub opt: Vec<String>,
+    /// Top level flags, common to all the profiles
+    pub flags: Flags,
     pub ignore: Vec<String>,
-    pub dev: BuildOption,
-    pub test: BuildOption,
-    // todo: we can also have a shared library or dynamic.
+    /// Profiles defined in the package file, by name
+    pub profiles: HashMap<String, BuildOption>,
     pub lib: Option<StaticLib>,
     /// Overriding auto detection of sources (path and regex)
     pub sources: Vec<String>,
@@ -168,7 +426,23 @@ pub struct Package {
     pub includes: Vec<String>,
 }
 
+/// Options of a build given in the command line.
+pub struct BuildFlags {
+    /// Name of the build profile
+    pub profile: String,
+    /// Compile all the independent units even if one failed, and report
+    /// every failure at the end.
+    pub keep_going: bool,
+    /// Print why each unit is rebuilt
+    pub explain: bool,
+    /// Maximum number of jobs running at the same time, override the
+    /// `jobs` setting
+    pub jobs: Option<usize>,
+    /// Fail if the lock file has to be updated
+    pub locked: bool,
+}
+
 pub struct PackagePaths {
-    pub header_folders: HashSet<PathBuf>,
-    pub source_files: HashSet<PathBuf>,
+    pub header_folders: BTreeSet<PathBuf>,
+    pub source_files: BTreeSet<PathBuf>,
 }
@@ -1,6 +1,8 @@
+use anyhow::Result;
 use clap::StructOpt;
-use cli::{Cli, Commands};
+use cli::{CacheSubCommand, Cli, Commands};
 use cmd::compile;
+use colored::Colorize;
 use common::types::Package;
 use settings::Settings;
 
@@ -21,27 +23,55 @@ fn main() {
     //       directly in parameter
     // todo: take second argument "build", "warn", "fmt", install.
     // todo: if unknown argument, search in ProjectDirs/bin the binary to call
-    // todo: nice unwrap handling
-    let settings = Settings::new().unwrap();
     let cli: &Cli = &cli::Cli::parse();
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
         .with_max_level(cli.verbosity())
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    // outputs are renamed into place when complete, nothing to clean
+    ctrlc::set_handler(|| {
+        cmd::kill_children();
+        eprintln!("{} interrupted", "error:".red().bold());
+        std::process::exit(130);
+    })
+    .expect("setting Ctrl-C handler failed");
+    if let Err(e) = run(cli) {
+        eprintln!("{} {e:#}", "error:".red().bold());
+        std::process::exit(1);
+    }
+}
+
+/// Execute the command given in the command line
+fn run(cli: &Cli) -> Result<()> {
+    let settings = Settings::new()?;
     if let Some(cmd) = &cli.command {
         match cmd {
             Commands::Build(cmd) => {
-                let root_pkg_file =
-                    Package::read(Some(DEFAULT_PACKAGE_FILE_NAME.to_string())).unwrap();
-                let level = cmd.compilation_level();
-                compile(&root_pkg_file, &settings, level).unwrap();
+                let root_pkg_file = Package::read(Some(DEFAULT_PACKAGE_FILE_NAME.to_string()))?;
+                compile(&root_pkg_file, &settings, &cmd.build_flags())?;
+            }
+            Commands::Run(cmd) => {
+                let root_pkg_file = Package::read(Some(DEFAULT_PACKAGE_FILE_NAME.to_string()))?;
+                cmd::run(
+                    &root_pkg_file,
+                    &settings,
+                    &cmd.build.build_flags(),
+                    &cmd.args,
+                )?;
             }
             Commands::New { name } => cmd::new(name),
+            Commands::Clean(cmd) => cmd::clean(&settings, &cmd.clean_flags())?,
+            Commands::Cache(cmd) => match cmd {
+                CacheSubCommand::List => cmd::cache::list(&settings)?,
+                CacheSubCommand::Path { package } => cmd::cache::path(&settings, package)?,
+                CacheSubCommand::Rm { package } => cmd::cache::rm(&settings, package)?,
+                CacheSubCommand::Prune { older_than } => cmd::cache::prune(&settings, *older_than)?,
+            },
             Commands::Test => {
-                let root_pkg_file =
-                    Package::read(Some(DEFAULT_PACKAGE_FILE_NAME.to_string())).unwrap();
-                cmd::test(&root_pkg_file, &settings).unwrap();
+                let root_pkg_file = Package::read(Some(DEFAULT_PACKAGE_FILE_NAME.to_string()))?;
+                cmd::test(&root_pkg_file, &settings)?;
             }
         };
     }
+    Ok(())
 }
@@ -1,8 +1,9 @@
 use crate::common::types::{
-    BuildOption, ConfigBuildOption, ConfigPackage, ConfigPkgDescription, DepVal, Package,
-    PkgDescription, SrcVal,
+    BuildOption, ConfigBuildOption, ConfigFlag, ConfigFlags, ConfigPackage, ConfigPkgDescription,
+    DepVal, Flag, Flags, LibKind, Package, PkgDescription, Scope, SrcVal,
 };
 use anyhow::{bail, Result};
+use std::{collections::HashMap, fmt, path::PathBuf};
 
 // todo: put all `deserializable` things into a sub package dedicated.
 //       - PkgFileInternal
@@ -16,10 +17,24 @@ impl TryFrom<ConfigPkgDescription> for PkgDescription {
             Some(t) => {
                 if let Ok(git) = t.clone().try_into() {
                     Some(SrcVal::Git(git))
-                } else if let Ok(local) = t.try_into() {
+                } else if let Ok(archive) = t.clone().try_into() {
+                    Some(SrcVal::Archive(archive))
+                } else if let Ok(local) = t.clone().try_into() {
+                    // the sources are compiled in place, they can't be patched
+                    if t.into_table().is_ok_and(|t| t.contains_key("patches")) {
+                        bail!(
+                            "`patches` can't be applied to the sources of {} given by a path, \
+                             they are used in place",
+                            p.name
+                        )
+                    }
                     Some(SrcVal::Local(local))
                 } else {
-                    bail!("unexpected src value")
+                    bail!(
+                        "unexpected src value of {}, expected {{ git = ... }}, \
+                         {{ url = ..., sha256 = ... }} or {{ path = ... }}",
+                        p.name
+                    )
                 }
             }
             None => None,
@@ -34,39 +49,139 @@ impl TryFrom<ConfigPkgDescription> for PkgDescription {
     }
 }
 
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Private => write!(f, "private"),
+            Scope::Public => write!(f, "public"),
+            Scope::Link => write!(f, "link"),
+        }
+    }
+}
+
+impl ConfigFlag {
+    /// Flag of a list whose default scope is `default`
+    fn scoped(self, default: Scope) -> Flag {
+        match self {
+            ConfigFlag::Value(value) => Flag {
+                value,
+                scope: default,
+            },
+            ConfigFlag::Scoped { value, scope } => Flag {
+                value,
+                scope: scope.unwrap_or(default),
+            },
+        }
+    }
+}
+
+/// Values of a list of flags only given to the link
+fn link_only(list: &str, flags: Vec<ConfigFlag>) -> Result<Vec<String>> {
+    flags
+        .into_iter()
+        .map(|f| match f.scoped(Scope::Link) {
+            Flag {
+                value,
+                scope: Scope::Link,
+            } => Ok(value),
+            Flag { value, scope } => {
+                bail!("{list} are only given to the link, {value} can't have the {scope} scope")
+            }
+        })
+        .collect()
+}
+
+impl TryFrom<ConfigFlags> for Flags {
+    type Error = anyhow::Error;
+    fn try_from(f: ConfigFlags) -> Result<Self, Self::Error> {
+        let private = |list: Vec<ConfigFlag>| -> Vec<Flag> {
+            list.into_iter().map(|f| f.scoped(Scope::Private)).collect()
+        };
+        let mut flags = Self {
+            cflags: private(f.cflags),
+            cxxflags: private(f.cxxflags),
+            defines: private(f.defines),
+            ldflags: link_only("ldflags", f.ldflags)?,
+            libs: link_only("libs", f.libs)?,
+        };
+        if let Some(define) = flags.defines.iter().find(|d| d.scope == Scope::Link) {
+            bail!(
+                "defines aren't given to the link, {} can't have the link scope",
+                define.value
+            )
+        }
+        // the legacy `opt` goes to the compilation of the package and to
+        // the link
+        for opt in f.opt {
+            flags.cflags.push(Flag {
+                value: opt.clone(),
+                scope: Scope::Private,
+            });
+            flags.ldflags.push(opt);
+        }
+        Ok(flags)
+    }
+}
+
 impl From<ConfigBuildOption> for BuildOption {
     fn from(b: ConfigBuildOption) -> Self {
         Self {
+            inherits: b.inherits,
+            opt_level: b.opt_level,
+            debug: b.debug,
+            flags: b.flags,
             ignore: b.ignore,
-            dependencies: DepVal::adapt(b.dependencies),
+            dependencies: DepVal::adapt(b.dependencies).unwrap_or_default(),
             sources: b.sources,
             includes: b.includes,
-            opt: b.opt,
         }
     }
 }
 
-impl From<ConfigPackage> for Package {
-    fn from(mut i: ConfigPackage) -> Self {
-        if i.dev.ignore.is_empty() {
-            i.dev.ignore.push(String::from("**/test.cpp"));
-        }
+impl TryFrom<ConfigPackage> for Package {
+    type Error = anyhow::Error;
+    fn try_from(mut i: ConfigPackage) -> Result<Self, Self::Error> {
         if i.ignore.is_empty() {
             i.ignore.push(String::from("**/test.cpp"));
         }
-        if i.test.ignore.is_empty() {
-            i.test.ignore.push(String::from("**/main.cpp"));
+        let mut profiles = HashMap::new();
+        for (name, legacy) in [("dev", i.dev), ("test", i.test)] {
+            if let Some(legacy) = legacy {
+                if i.profile.contains_key(name) {
+                    bail!(
+                        "[{name}] and [profile.{name}] can't be both defined, use [profile.{name}]"
+                    )
+                }
+                profiles.insert(name.to_string(), legacy.into());
+            }
         }
-        Self {
+        profiles.extend(i.profile.into_iter().map(|(k, v)| (k, v.into())));
+        if let Some(lib) = &mut i.lib {
+            if lib.kind == LibKind::HeaderOnly {
+                if lib.prebuilt.is_some() {
+                    bail!("a header-only library can't be prebuilt")
+                }
+                // there is no compilation of its own
+                for flag in lib.flags.cflags.iter_mut().chain(&mut lib.flags.cxxflags) {
+                    if flag.scope == Scope::Private {
+                        flag.scope = Scope::Public;
+                    }
+                }
+                for define in &mut lib.flags.defines {
+                    define.scope = Scope::Public;
+                }
+            }
+        }
+        Ok(Self {
+            file: PathBuf::new(),
             dependencies: DepVal::adapt(i.dependencies),
-            dev: i.dev.into(),
-            test: i.test.into(),
+            profiles,
             ignore: i.ignore,
             lib: i.lib,
-            opt: i.opt,
+            flags: i.flags,
             sources: i.package.sources.clone(),
             includes: i.package.includes.clone(),
-            pkg_description: i.package.try_into().unwrap(),
-        }
+            pkg_description: i.package.try_into()?,
+        })
     }
 }
@@ -1,13 +1,14 @@
 use crate::common::tools::unwrap_path_patterns;
 use crate::common::types::{
-    ConfigPackage, DepVal, Dependency, GitTarget, LocalTarget, Package, PackagePaths, StaticLib,
+    BuildOption, ConfigPackage, DepVal, Dependency, Flag, Flags, GitTarget, LibKind, LocalTarget,
+    Package, PackagePaths, Prebuilt, Profile, Scope, SrcVal, StaticLib, SystemTarget,
 };
 use crate::settings::Settings;
 use crate::DEFAULT_PACKAGE_FILE_NAME;
 use anyhow::{bail, Result};
 use config::{Config, File, Value};
 use glob::Pattern;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
 use tracing::debug;
 use walkdir::WalkDir;
@@ -22,6 +23,8 @@ impl DepVal {
                 ret.insert(k, DepVal::Version(version));
             } else if let Ok(local) = v.clone().try_into::<LocalTarget>() {
                 ret.insert(k, DepVal::Path(PathBuf::from(local.path)));
+            } else if let Ok(system) = v.clone().try_into::<SystemTarget>() {
+                ret.insert(k, DepVal::System(system));
             } else if let Ok(git) = v.try_into::<GitTarget>() {
                 ret.insert(k, DepVal::Git(git));
             }
@@ -34,21 +37,66 @@ impl Package {
     /// Read a package file with name [DEFAULT_PACKAGE_FILE_NAME] or with the
     /// given `path`.
     pub fn read(path: Option<String>) -> Result<Self> {
+        let path = path.unwrap_or_else(|| DEFAULT_PACKAGE_FILE_NAME.to_string());
         let mut pkg_cfg = Config::default();
-        pkg_cfg.merge(File::with_name(
-            &path.unwrap_or_else(|| DEFAULT_PACKAGE_FILE_NAME.to_string()),
-        ))?;
+        pkg_cfg.merge(File::with_name(&path))?;
         //todo: if lib, shared lib, dyn lib a defined in the same pkg file, print warning
         let internal: ConfigPackage = pkg_cfg.try_into()?;
-        Ok(internal.into())
+        let mut package: Package = internal.try_into()?;
+        package.file = PathBuf::from(path);
+        package.resolve_paths();
+        Ok(package)
+    }
+
+    /// Make the paths of the package file, a local archive source and the
+    /// `patches` of the sources and the git dependencies of all the
+    /// profiles, relative to its folder instead of the current one.
+    fn resolve_paths(&mut self) {
+        let dir = self.file.parent().unwrap_or(Path::new("")).to_path_buf();
+        if let Some(SrcVal::Archive(archive)) = &mut self.pkg_description.src {
+            if !archive.url.contains("://") {
+                archive.url = dir.join(&archive.url).to_string_lossy().to_string();
+            }
+            for patch in &mut archive.patches {
+                *patch = dir.join(&*patch);
+            }
+        }
+        let sources = self
+            .pkg_description
+            .src
+            .iter_mut()
+            .filter_map(|src| match src {
+                SrcVal::Git(git) => Some(git),
+                _ => None,
+            });
+        // the legacy `[dev]` and `[test]` are profiles too
+        let dependencies = self
+            .dependencies
+            .iter_mut()
+            .flat_map(|d| d.values_mut())
+            .chain(
+                self.profiles
+                    .values_mut()
+                    .flat_map(|p| p.dependencies.values_mut()),
+            )
+            .filter_map(|d| match d {
+                DepVal::Git(git) => Some(git),
+                _ => None,
+            });
+        for git in sources.chain(dependencies) {
+            for patch in &mut git.patches {
+                *patch = dir.join(&*patch);
+            }
+        }
     }
 
     /// Return an owned pathbuf to the target directory.
     ///
-    /// Target is computed with format ${cache_dir}/${pkg_name}_${pkg_version}
+    /// Target is computed with format
+    /// ${cache_dir}/build/${pkg_name}_${pkg_version}
     pub fn target_dir(&self, settings: &Settings) -> PathBuf {
         // todo: memoization
-        let mut p = settings.project_dirs.cache_dir().to_path_buf();
+        let mut p = settings.cache_dir.join("build");
         p.push(format!(
             "{}_{}",
             self.pkg_description.name, self.pkg_description.version
@@ -56,14 +104,20 @@ impl Package {
         p
     }
 
+    /// Folder of the objects and libraries built with the `profile`, in the
+    /// target directory. Each profile has its own objects.
+    pub fn profile_dir(&self, settings: &Settings, profile: &str) -> PathBuf {
+        self.target_dir(settings).join(profile)
+    }
+
     /// Create all directories and return the .o path respectiv to the given
-    /// `src_file`.
+    /// `src_file`, built with the `profile`.
     ///
     /// Example:
     /// ${cache_dir}/${pkg_name}/${rel_src_file_path.cpp} =>
-    /// ${cache_dir}/${target_dir}/${rel_src_file_path.o}
-    pub fn object_path(&self, src_file: &Path, settings: &Settings) -> PathBuf {
-        let mut target_dir = self.target_dir(settings);
+    /// ${cache_dir}/${target_dir}/${profile}/${rel_src_file_path.o}
+    pub fn object_path(&self, src_file: &Path, settings: &Settings, profile: &str) -> PathBuf {
+        let mut target_dir = self.profile_dir(settings, profile);
         target_dir.push(src_file);
         let ret = target_dir.with_extension("o");
         target_dir.pop();
@@ -71,57 +125,63 @@ impl Package {
         ret
     }
 
-    /// Get the dependences of the packages corresponding to the
-    /// given compile level.
+    /// Resolve the build profile `name` with its inheritance. See
+    /// [BuildOption::builtin] for the default profiles.
     ///
-    /// Note: dependencies are comulative, e.g. `test` level contains
-    ///       also `dev` and `release` dependencies.
-    pub fn get_dependencies(&self, compile_level: usize) -> VecDeque<Dependency> {
-        let mut map = HashMap::new();
-        if self.dependencies.is_some() && compile_level >= 1 {
-            map.extend(
-                self.dependencies
-                    .clone()
-                    .unwrap()
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone())),
-            )
-        }
-        if self.dev.dependencies.is_some() && compile_level >= 2 {
-            map.extend(
-                self.dev
-                    .dependencies
-                    .clone()
-                    .unwrap()
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone())),
-            );
+    /// The profile starts from the top level options of the package (the
+    /// `ignore`, flags, `sources`, `includes` and `[dependencies]`, and the
+    /// flags of its `[lib]`) then each profile from the farthest parent to
+    /// `name` is applied:
+    /// - `opt-level` and `debug` are inherited unless set,
+    /// - the flags and `dependencies` accumulate,
+    /// - `ignore`, `sources` and `includes` replace the inherited ones when
+    ///   set.
+    ///
+    /// `test` inherits `dev` and a custom profile inherits `dev` unless it
+    /// sets `inherits`, `release` and `dev` only inherit the top level.
+    pub fn profile(&self, name: &str) -> Result<Profile> {
+        let mut chain = vec![];
+        let mut next = Some(name.to_string());
+        while let Some(name) = next {
+            if chain.iter().any(|(n, _)| n == &name) {
+                bail!("profile {name} inherits from itself")
+            }
+            let option = match (BuildOption::builtin(&name), self.profiles.get(&name)) {
+                (Some(builtin), Some(option)) => builtin.overlay(option),
+                (Some(builtin), None) => builtin,
+                (None, Some(option)) => option.clone(),
+                (None, None) => bail!(
+                    "unknown profile {name}, define it with a [profile.{name}] section in {}",
+                    self.file.to_string_lossy()
+                ),
+            };
+            next = match (&option.inherits, name.as_str()) {
+                (Some(parent), _) => Some(parent.clone()),
+                (None, "release" | "dev") => None,
+                (None, _) => Some("dev".to_string()),
+            };
+            chain.push((name, option));
         }
-        if self.test.dependencies.is_some() && compile_level == 3 {
-            map.extend(
-                self.test
-                    .dependencies
-                    .clone()
-                    .unwrap()
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone())),
-            );
+        Profile::check_name(name)?;
+        let mut flags = self.flags.clone();
+        if let Some(lib) = &self.lib {
+            flags.extend(&lib.flags);
         }
-        VecDeque::from_iter(map.iter().map(|(pkg_name, value)| Dependency {
-            name: pkg_name.clone(),
-            desc: value.clone(),
-        }))
-    }
-
-    /// Get patterns of files and folders we want to ignores corresponding to
-    /// the compilation level. 1: release, 2: dev, 3: test. Ignore doesn't
-    /// inherits like the dependencies.
-    pub fn get_ignore(&self, compile_level: usize) -> &Vec<String> {
-        match compile_level {
-            2 => &self.dev.ignore,
-            3 => &self.test.ignore,
-            _ => &self.ignore,
+        let mut profile = Profile {
+            name: name.to_string(),
+            opt_level: "0".to_string(),
+            debug: false,
+            flags,
+            options: Flags::default(),
+            ignore: self.ignore.clone(),
+            dependencies: self.dependencies.clone().unwrap_or_default(),
+            sources: self.sources.clone(),
+            includes: self.includes.clone(),
+        };
+        for (_, option) in chain.iter().rev() {
+            profile.apply(option);
         }
+        Ok(profile)
     }
 
     /// Return a library or an error if the lib is None
@@ -135,35 +195,6 @@ impl Package {
         }
     }
 
-    /// Get the opt of the current package. Ignore doesn't
-    /// inherits like the dependencies.
-    pub fn get_opt(&self, compile_level: usize) -> Vec<String> {
-        if let Some(lib) = &self.lib {
-            return lib.opt.clone();
-        }
-        match compile_level {
-            2 => self.dev.opt.clone(),
-            3 => self.test.opt.clone(),
-            _ => self.opt.clone(),
-        }
-    }
-
-    pub fn get_sources(&self, compile_level: usize) -> &Vec<String> {
-        match compile_level {
-            2 => &self.dev.sources,
-            3 => &self.test.sources,
-            _ => &self.sources,
-        }
-    }
-
-    pub fn get_includes(&self, compile_level: usize) -> &Vec<String> {
-        match compile_level {
-            2 => &self.dev.includes,
-            3 => &self.test.includes,
-            _ => &self.includes,
-        }
-    }
-
     /// Determine the headers folder that need to be included and the sources
     /// that need to be build. Take the `local_path` that is the deduced path
     /// of the package repository (after being loaded if necessary).
@@ -175,15 +206,14 @@ impl Package {
     /// of any `.h` file. A package can overides these extensions with the `build`
     /// and `include` parameters. Inverse is also possible, all path containing
     /// something in the `ignore` parameter in package file will be really ignored.
-    fn get_paths(&self, local_path: &Path, compile_level: usize) -> Result<PackagePaths> {
+    fn get_paths(&self, local_path: &Path, ignore: &[String]) -> Result<PackagePaths> {
         // todo: return an error if a cyclic path found.
         // todo: add `ignore` parameter in pkg_file.
         // todo: find any file that end with the given extensions from pkg_file.
         debug!("package path walk from {}", local_path.to_string_lossy());
-        let mut source_files = HashSet::new();
-        let mut header_folders = HashSet::new();
+        let mut source_files = BTreeSet::new();
+        let mut header_folders = BTreeSet::new();
         let base = local_path.to_path_buf();
-        let ignore = self.get_ignore(compile_level);
         'walk: for entry in WalkDir::new(local_path)
             .follow_links(true)
             .into_iter()
@@ -213,12 +243,12 @@ impl Package {
         })
     }
 
-    pub fn root_package_paths(&self, compile_level: usize) -> Result<PackagePaths> {
+    pub fn root_package_paths(&self, profile: &Profile) -> Result<PackagePaths> {
         let local_path = Path::new(".");
-        let mut source_files = unwrap_path_patterns(local_path, self.get_sources(compile_level));
-        let mut header_folders = unwrap_path_patterns(local_path, self.get_includes(compile_level));
+        let mut source_files = unwrap_path_patterns(local_path, &profile.sources);
+        let mut header_folders = unwrap_path_patterns(local_path, &profile.includes);
         if source_files.is_empty() || header_folders.is_empty() {
-            let pkg_paths = self.get_paths(local_path, compile_level).unwrap();
+            let pkg_paths = self.get_paths(local_path, &profile.ignore)?;
             if source_files.is_empty() {
                 source_files = pkg_paths.source_files;
             }
@@ -250,7 +280,7 @@ impl Package {
         // todo: replace unwraps with error management
 
         if source_files.is_empty() || header_folders.is_empty() {
-            let pkg_paths = self.get_paths(local_path, 0)?;
+            let pkg_paths = self.get_paths(local_path, &self.ignore)?;
             if source_files.is_empty() {
                 source_files = pkg_paths.source_files;
             }
@@ -265,3 +295,227 @@ impl Package {
         })
     }
 }
+
+impl BuildOption {
+    /// Default options of the built-in profiles:
+    /// - `release`: `-O2 -DNDEBUG`
+    /// - `dev`: `-O0 -g`
+    /// - `test`: inherits `dev`, ignores the `main.cpp` files
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "release" => Some(Self {
+                opt_level: Some("2".to_string()),
+                debug: Some(false),
+                flags: Flags {
+                    defines: vec![Flag {
+                        value: "NDEBUG".to_string(),
+                        scope: Scope::Private,
+                    }],
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            "dev" => Some(Self {
+                opt_level: Some("0".to_string()),
+                debug: Some(true),
+                ..Default::default()
+            }),
+            "test" => Some(Self {
+                inherits: Some("dev".to_string()),
+                ignore: Some(vec![String::from("**/main.cpp")]),
+                ..Default::default()
+            }),
+            _ => None,
+        }
+    }
+
+    /// Options of a built-in profile redefined by the package file
+    fn overlay(mut self, other: &BuildOption) -> Self {
+        self.inherits = other.inherits.clone().or(self.inherits);
+        self.opt_level = other.opt_level.clone().or(self.opt_level);
+        self.debug = other.debug.or(self.debug);
+        self.flags.extend(&other.flags);
+        self.ignore = other.ignore.clone().or(self.ignore);
+        self.dependencies.extend(other.dependencies.clone());
+        self.sources = other.sources.clone().or(self.sources);
+        self.includes = other.includes.clone().or(self.includes);
+        self
+    }
+}
+
+impl Profile {
+    /// Fail unless `name` is a valid profile name, made of letters, digits,
+    /// `-` and `_`. It's the name of the output folder `target/<name>`.
+    pub fn check_name(name: &str) -> Result<()> {
+        let valid = name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if name.is_empty() || !valid {
+            bail!("invalid profile name {name:?}")
+        }
+        Ok(())
+    }
+
+    /// Apply the options of a child profile
+    fn apply(&mut self, option: &BuildOption) {
+        if let Some(opt_level) = &option.opt_level {
+            self.opt_level = opt_level.clone();
+        }
+        if let Some(debug) = option.debug {
+            self.debug = debug;
+        }
+        self.flags.extend(&option.flags);
+        self.options.extend(&option.flags);
+        if let Some(ignore) = &option.ignore {
+            self.ignore = ignore.clone();
+        }
+        self.dependencies.extend(option.dependencies.clone());
+        if let Some(sources) = &option.sources {
+            self.sources = sources.clone();
+        }
+        if let Some(includes) = &option.includes {
+            self.includes = includes.clone();
+        }
+    }
+
+    /// Gcc arguments of the compilation of the source `src` of the root
+    /// package with the profile
+    pub fn compile_args(&self, src: &Path) -> Vec<String> {
+        let mut args = vec![format!("-O{}", self.opt_level)];
+        if self.debug {
+            args.push("-g".to_string());
+        }
+        args.extend(self.flags.compile_args(src, true));
+        args
+    }
+
+    /// Gcc arguments of the compilation of the source `src` of a dependency
+    /// with the profile: its optimization, debug and flags, without the
+    /// flags of the root package.
+    pub fn dependency_args(&self, src: &Path) -> Vec<String> {
+        let mut args = vec![format!("-O{}", self.opt_level)];
+        if self.debug {
+            args.push("-g".to_string());
+        }
+        args.extend(self.options.compile_args(src, true));
+        args
+    }
+
+    /// `CMAKE_BUILD_TYPE` closest to the optimization and the debug of the
+    /// profile
+    pub fn cmake_build_type(&self) -> &'static str {
+        match (self.debug, self.opt_level.as_str()) {
+            (true, "0") => "Debug",
+            (true, _) => "RelWithDebInfo",
+            (false, "s" | "z") => "MinSizeRel",
+            (false, _) => "Release",
+        }
+    }
+
+    /// Dependencies required by the profile
+    pub fn get_dependencies(&self) -> Vec<Dependency> {
+        self.dependencies
+            .iter()
+            .map(|(name, desc)| Dependency {
+                name: name.clone(),
+                desc: desc.clone(),
+            })
+            .collect()
+    }
+}
+
+impl Flags {
+    /// Add the flags of `other` after ours
+    pub fn extend(&mut self, other: &Flags) {
+        self.cflags.extend(other.cflags.iter().cloned());
+        self.cxxflags.extend(other.cxxflags.iter().cloned());
+        self.defines.extend(other.defines.iter().cloned());
+        self.ldflags.extend(other.ldflags.iter().cloned());
+        self.libs.extend(other.libs.iter().cloned());
+    }
+
+    /// Gcc arguments of the compilation of the source `src`, of the package
+    /// itself if `own`, of a package depending on it otherwise. The
+    /// `cxxflags` are skipped for C sources.
+    pub fn compile_args(&self, src: &Path, own: bool) -> Vec<String> {
+        let cxx = src.extension().is_none_or(|e| e != "c");
+        let wanted = |f: &&Flag| f.scope == Scope::Public || (own && f.scope == Scope::Private);
+        let mut args: Vec<String> = self
+            .defines
+            .iter()
+            .filter(wanted)
+            .map(|d| format!("-D{}", d.value))
+            .collect();
+        args.extend(self.cflags.iter().filter(wanted).map(|f| f.value.clone()));
+        if cxx {
+            args.extend(self.cxxflags.iter().filter(wanted).map(|f| f.value.clone()));
+        }
+        args
+    }
+
+    /// Gcc arguments of the link given before the objects, the `ldflags` and
+    /// the `cflags` and `cxxflags` with the `link` scope.
+    pub fn link_args(&self) -> Vec<String> {
+        self.cflags
+            .iter()
+            .chain(&self.cxxflags)
+            .filter(|f| f.scope == Scope::Link)
+            .map(|f| f.value.clone())
+            .chain(self.ldflags.iter().cloned())
+            .collect()
+    }
+
+    /// Gcc arguments of the libraries, given after the objects
+    pub fn lib_args(&self) -> Vec<String> {
+        self.libs
+            .iter()
+            .map(|l| {
+                if l.starts_with('-') || l.contains('/') {
+                    l.clone()
+                } else {
+                    format!("-l{l}")
+                }
+            })
+            .collect()
+    }
+
+    /// Libraries of the link of all the `flags`, given in the link order. A
+    /// library given several times only keeps its last occurrence, after
+    /// all the libraries needing it.
+    pub fn link_libs(flags: &[&Flags]) -> Vec<String> {
+        let mut libs: Vec<String> = vec![];
+        for arg in flags.iter().flat_map(|f| f.lib_args()) {
+            libs.retain(|l| l != &arg);
+            libs.push(arg);
+        }
+        libs
+    }
+}
+
+impl Prebuilt {
+    /// Entry of the architecture `arch`, its keys override the common ones
+    pub fn for_arch(&self, arch: &str) -> Prebuilt {
+        let common = self.clone();
+        match self.arch.get(arch) {
+            Some(entry) => Prebuilt {
+                static_lib: entry.static_lib.clone().or(common.static_lib),
+                shared: entry.shared.clone().or(common.shared),
+                headers: entry.headers.clone().or(common.headers),
+                sha256: entry.sha256.clone().or(common.sha256),
+                arch: HashMap::new(),
+            },
+            None => common,
+        }
+    }
+
+    /// Library to link and its kind. The one of `kind` is preferred when
+    /// both a static and a shared library are given.
+    pub fn library(&self, kind: LibKind) -> Option<(&str, LibKind)> {
+        let static_lib = self.static_lib.as_deref().map(|l| (l, LibKind::Static));
+        let shared = self.shared.as_deref().map(|l| (l, LibKind::Shared));
+        match kind {
+            LibKind::Shared => shared.or(static_lib),
+            _ => static_lib.or(shared),
+        }
+    }
+}
@@ -1,11 +1,14 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use config::{Config, File, Value};
 use directories::ProjectDirs;
 use serde_derive::Deserialize;
 
-use crate::common::types::{GitTarget, LocalTarget};
+use crate::common::{
+    tools::parse_size,
+    types::{GitTarget, LocalTarget},
+};
 
 /// An overlay in a folder or a git repository that have a folder architecture
 /// like:
@@ -19,6 +22,7 @@ use crate::common::types::{GitTarget, LocalTarget};
 ///     pkg_version2.toml
 ///     ...
 /// ...
+#[derive(Clone)]
 pub enum Layer {
     Git(GitTarget),
     Dir(LocalTarget),
@@ -29,7 +33,11 @@ struct SettingFile {
     // todo: g++ binary direcory
     #[serde(default)]
     pub layers: Vec<Value>,
-    // todo: conservation of .o, cache management
+    /// Default number of parallel jobs
+    pub jobs: Option<usize>,
+    /// Size of the cache above which the least recently used entries are
+    /// removed after a build, e.g. `10G`
+    pub max_cache_size: Option<String>,
     // todo: compiler default flags
 }
 
@@ -50,9 +58,14 @@ impl SettingFile {
     }
 }
 
+#[derive(Clone)]
 pub struct Settings {
     pub layers: Vec<Layer>,
-    pub project_dirs: ProjectDirs,
+    pub jobs: Option<usize>,
+    /// Maximum size of the cache in bytes
+    pub max_cache_size: Option<u64>,
+    /// Cache of the user, shared by all the projects
+    pub cache_dir: PathBuf,
 }
 
 /// Initialisation for the first use of chataigne
@@ -87,7 +100,14 @@ impl Settings {
 
         Ok(Settings {
             layers: setting_file.get_layers(),
-            project_dirs,
+            jobs: setting_file.jobs,
+            max_cache_size: setting_file
+                .max_cache_size
+                .as_deref()
+                .map(parse_size)
+                .transpose()
+                .context("invalid max_cache_size in the settings")?,
+            cache_dir: project_dirs.cache_dir().to_path_buf(),
         })
     }
 }
@@ -0,0 +1,111 @@
+use super::{read_toml, try_read_toml};
+use crate::{
+    cmd::archive::unpack,
+    common::types::{ArchiveTarget, SrcVal},
+};
+use std::{path::Path, process::Command};
+
+/// Create `foo-1.2.tar.gz` in `dir`, containing `foo-1.2/foo.c`. Return
+/// its url and hash.
+fn tarball(dir: &Path) -> (String, String) {
+    let src = dir.join("foo-1.2");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("foo.c"), "int foo(void) { return 1; }\n").unwrap();
+    let status = Command::new("tar")
+        .args(["-czf", "foo-1.2.tar.gz", "foo-1.2"])
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let file = dir.join("foo-1.2.tar.gz");
+    let hash = sha256::digest_bytes(&std::fs::read(&file).unwrap());
+    (format!("file://{}", file.display()), hash)
+}
+
+#[test]
+fn unpack_a_local_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, sha256) = tarball(dir.path());
+    let archive = ArchiveTarget {
+        url,
+        sha256,
+        strip_prefix: Some("foo-1.2".into()),
+        patches: vec![],
+    };
+    let dest = dir.path().join("src");
+    unpack(&archive, &dir.path().join("tmp"), &dest).unwrap();
+    assert!(dest.join("foo.c").is_file());
+}
+
+#[test]
+fn archive_with_another_hash_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, _) = tarball(dir.path());
+    let archive = ArchiveTarget {
+        url,
+        sha256: "0".repeat(64),
+        strip_prefix: None,
+        patches: vec![],
+    };
+    let dest = dir.path().join("src");
+    let err = unpack(&archive, &dir.path().join("tmp"), &dest).unwrap_err();
+    assert!(err.to_string().contains("checksum mismatch"), "{err}");
+    assert!(!dest.exists());
+}
+
+#[test]
+fn patches_are_applied_to_the_extracted_sources() {
+    let dir = tempfile::tempdir().unwrap();
+    let (url, sha256) = tarball(dir.path());
+    let patch = |name: &str, from: &str, to: &str| {
+        let path = dir.path().join(name);
+        let diff = format!(
+            "--- a/foo.c\n+++ b/foo.c\n@@ -1 +1 @@\n-int foo(void) {{ return {from}; }}\n\
+             +int foo(void) {{ return {to}; }}\n"
+        );
+        std::fs::write(&path, diff).unwrap();
+        path
+    };
+    let mut archive = ArchiveTarget {
+        url,
+        sha256,
+        strip_prefix: Some("foo-1.2".into()),
+        patches: vec![patch("fix.patch", "1", "2")],
+    };
+    let dest = dir.path().join("src");
+    unpack(&archive, &dir.path().join("tmp"), &dest).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dest.join("foo.c")).unwrap(),
+        "int foo(void) { return 2; }\n"
+    );
+
+    // sources a patch doesn't apply to never reach the cache
+    archive.patches = vec![patch("old.patch", "0", "2")];
+    let dest = dir.path().join("other");
+    let err = unpack(&archive, &dir.path().join("tmp"), &dest).unwrap_err();
+    assert!(err.to_string().contains("doesn't apply"), "{err}");
+    assert!(!dest.exists());
+}
+
+#[test]
+fn patches_of_an_archive_or_a_path_source() {
+    let package = read_toml(
+        "[package]\nname=\"foo\"\nversion=\"1.2.0\"\n\
+         src={url=\"foo-1.2.tar.gz\", sha256=\"0\", patches=[\"fix.patch\"]}\n",
+    );
+    match &package.pkg_description.src {
+        Some(SrcVal::Archive(archive)) => {
+            let dir = package.file.parent().unwrap();
+            assert_eq!(archive.patches, [dir.join("fix.patch")]);
+        }
+        _ => panic!("expected an archive source"),
+    }
+
+    let err = try_read_toml(
+        "[package]\nname=\"foo\"\nversion=\"1.2.0\"\n\
+         src={path=\"libs/foo\", patches=[\"fix.patch\"]}\n",
+    )
+    .err()
+    .unwrap();
+    assert!(err.to_string().contains("used in place"), "{err}");
+}
@@ -0,0 +1,142 @@
+use super::{build_flags, in_dir, read_package, temp_settings, write_files};
+use crate::cmd::{compile, test};
+use std::{path::Path, process::Command};
+
+#[test]
+fn layer_package_without_sources() {
+    let dir = tempfile::tempdir().unwrap();
+    let layer = dir.path().join("layer");
+    // a header-only package shipped in the layer, next to its package file
+    write_files(
+        &layer.join("version"),
+        &[
+            (
+                "1.0.0.toml",
+                "[package]\nname=\"version\"\nversion=\"1.0.0\"\n[lib]\nheaders=[\"include\"]\n",
+            ),
+            ("include/version.h", "#define VERSION 7\n"),
+        ],
+    );
+    let project = dir.path().join("app");
+    write_files(
+        &project,
+        &[
+            (
+                "chataigne.toml",
+                "[package]\nname=\"app\"\nversion=\"0.1.0\"\n[dependencies]\nversion=\"1\"\n",
+            ),
+            (
+                "src/main.cpp",
+                "#include <version.h>\nint main() { return VERSION; }\n",
+            ),
+        ],
+    );
+    let settings = temp_settings(dir.path(), &[&layer]);
+    in_dir(&project, || {
+        compile(&read_package(&project), &settings, &build_flags("dev")).unwrap();
+        // built twice, the sources of the layer are still there
+        compile(&read_package(&project), &settings, &build_flags("dev")).unwrap();
+    });
+    let status = Command::new(project.join("target/dev/app"))
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(7));
+    assert!(!settings.cache_dir.join("src").exists());
+}
+
+#[test]
+fn ch_test_builds_without_main_and_runs_the_tests() {
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().join("app");
+    write_files(
+        &project,
+        &[
+            (
+                "chataigne.toml",
+                "[package]\nname=\"app\"\nversion=\"0.1.0\"\n",
+            ),
+            ("src/main.cpp", "int main() { return 1; }\n"),
+            ("src/add.cpp", "int add(int a, int b) { return a + b; }\n"),
+            (
+                "tests/add_test.cpp",
+                "#include <fstream>\nint add(int a, int b);\n\
+                 int main() { std::ofstream(\"ran\") << add(2, 3); return add(2, 3) != 5; }\n",
+            ),
+        ],
+    );
+    let settings = temp_settings(dir.path(), &[]);
+    in_dir(&project, || {
+        test(&read_package(&project), &settings).unwrap()
+    });
+    assert!(project.join("target/test/app").is_file());
+    assert!(project.join("target/test/tests/add_test.o").is_file());
+    assert!(!project.join("target/test/src/main.o").exists());
+    assert!(!project.join("target/dev").exists());
+    assert_eq!(std::fs::read_to_string(project.join("ran")).unwrap(), "5");
+
+    std::fs::write(
+        project.join("src/add.cpp"),
+        "int add(int a, int b) { return a - b; }\n",
+    )
+    .unwrap();
+    let err = in_dir(&project, || {
+        test(&read_package(&project), &settings).unwrap_err()
+    });
+    assert!(err.to_string().starts_with("tests of app failed"), "{err}");
+}
+
+#[test]
+fn path_dependency_shared_by_two_projects() {
+    let dir = tempfile::tempdir().unwrap();
+    write_files(
+        &dir.path().join("lib"),
+        &[
+            (
+                "chataigne.toml",
+                "[package]\nname=\"lib\"\nversion=\"1.0.0\"\n[lib]\n",
+            ),
+            ("src/lib.cpp", "int answer() { return 4; }\n"),
+        ],
+    );
+    let project = |path: &str, dep: &str| {
+        let project = dir.path().join(path);
+        write_files(
+            &project,
+            &[
+                (
+                    "chataigne.toml",
+                    &format!(
+                        "[package]\nname=\"app\"\nversion=\"0.1.0\"\n\
+                         [dependencies]\nlib={{path=\"{dep}\"}}\n"
+                    ),
+                ),
+                (
+                    "src/main.cpp",
+                    "int answer();\nint main() { return answer(); }\n",
+                ),
+            ],
+        );
+        project
+    };
+    // the same dependency, reached with two relative paths
+    let a = project("a", "../lib");
+    let b = project("nested/b", "../../lib");
+    let settings = temp_settings(dir.path(), &[]);
+    let build = |project: &Path| {
+        in_dir(project, || {
+            compile(&read_package(project), &settings, &build_flags("dev")).unwrap()
+        })
+    };
+    build(&a);
+    let obj = settings.cache_dir.join("build/lib_1.0.0/dev/src/lib.o");
+    let built = std::fs::metadata(&obj).unwrap().modified().unwrap();
+    build(&b);
+    build(&a);
+    assert_eq!(std::fs::metadata(&obj).unwrap().modified().unwrap(), built);
+    for project in [a, b] {
+        let status = Command::new(project.join("target/dev/app"))
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(4));
+    }
+}
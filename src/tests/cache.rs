@@ -0,0 +1,21 @@
+use crate::common::tools::{format_size, parse_duration, parse_size};
+use std::time::Duration;
+
+#[test]
+fn sizes_and_durations() {
+    assert_eq!(parse_size("1234").unwrap(), 1234);
+    assert_eq!(parse_size("500M").unwrap(), 500 << 20);
+    assert_eq!(parse_size("10GiB").unwrap(), 10 << 30);
+    assert!(parse_size("10X").is_err());
+    assert_eq!(format_size(512), "512 B");
+    assert_eq!(format_size(3 << 19), "1.5 MiB");
+    assert_eq!(
+        parse_duration("30d").unwrap(),
+        Duration::from_secs(30 * 86400)
+    );
+    assert_eq!(
+        parse_duration("12h").unwrap(),
+        Duration::from_secs(12 * 3600)
+    );
+    assert!(parse_duration("30").is_err());
+}
@@ -0,0 +1,124 @@
+use super::{build_flags, in_dir, read_package, temp_settings, write_files};
+use crate::{
+    cmd::compile,
+    common::checksum::{fingerprint_path, parse_depfile, stale, temp_path, Fingerprint},
+};
+use std::{
+    ffi::CString,
+    path::{Path, PathBuf},
+};
+
+#[test]
+fn depfile_prerequisites() {
+    let depfile = "target/main.o: main.cpp include/a\\ b.h \\\n include/c.h\n";
+    assert_eq!(
+        parse_depfile(depfile),
+        vec![
+            PathBuf::from("main.cpp"),
+            PathBuf::from("include/a b.h"),
+            PathBuf::from("include/c.h"),
+        ]
+    );
+}
+
+#[test]
+fn stale_fingerprint() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("main.cpp");
+    let obj = dir.path().join("main.o");
+    std::fs::write(&src, "int main() {}").unwrap();
+    assert_eq!(stale(&obj, "gcc", "v1").unwrap(), "output is missing");
+    std::fs::write(&obj, "").unwrap();
+    assert_eq!(stale(&obj, "gcc", "v1").unwrap(), "no fingerprint");
+
+    let fingerprint = Fingerprint::new("gcc", "v1", vec![src.clone()]).unwrap();
+    fingerprint.save(&obj).unwrap();
+    assert_eq!(Fingerprint::load(&obj).unwrap(), fingerprint);
+    assert!(stale(&obj, "gcc", "v1").is_none());
+    assert_eq!(
+        stale(&obj, "gcc -O2", "v1").unwrap(),
+        "command line changed"
+    );
+    assert_eq!(
+        stale(&obj, "gcc", "v2").unwrap(),
+        "compiler version changed"
+    );
+    std::fs::write(&src, "int main() { return 1; }").unwrap();
+    assert!(stale(&obj, "gcc", "v1")
+        .unwrap()
+        .ends_with("main.cpp changed"));
+}
+
+/// Kill the process group of the running child of the tests whose command
+/// line contains `pattern`, wait for it to be started. The child is blocked
+/// on the pipe `fifo`, which is opened to release it if it's not found.
+fn kill_child(pattern: &str, fifo: &Path) {
+    let me = std::process::id().to_string();
+    for _ in 0..500 {
+        for entry in std::fs::read_dir("/proc").unwrap().filter_map(|e| e.ok()) {
+            let proc = entry.path();
+            let stat = std::fs::read_to_string(proc.join("stat")).unwrap_or_default();
+            // pid (comm) state ppid ...
+            let ppid = stat
+                .rsplit(')')
+                .next()
+                .and_then(|s| s.split_whitespace().nth(1));
+            let cmdline = std::fs::read(proc.join("cmdline")).unwrap_or_default();
+            if ppid == Some(&me) && String::from_utf8_lossy(&cmdline).contains(pattern) {
+                let pid: i32 = entry.file_name().to_string_lossy().parse().unwrap();
+                unsafe {
+                    libc::kill(-pid, libc::SIGTERM);
+                }
+                return;
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    let _ = std::fs::write(fifo, "");
+    panic!("no child running {pattern}");
+}
+
+#[test]
+fn interrupted_build_leaves_no_partial_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().join("app");
+    write_files(
+        &project,
+        &[
+            (
+                "chataigne.toml",
+                "[package]\nname=\"app\"\nversion=\"0.1.0\"\n",
+            ),
+            ("src/interrupted.cpp", "int main() { return 0; }\n"),
+        ],
+    );
+    let settings = temp_settings(dir.path(), &[]);
+    let build = || compile(&read_package(&project), &settings, &build_flags("dev"));
+    let obj = project.join("target/dev/src/interrupted.o");
+    in_dir(&project, || build().unwrap());
+    let complete = std::fs::read(&obj).unwrap();
+
+    // gcc waits on the include of a pipe, it's killed while the object is
+    // being built
+    let fifo = project.join("src/slow.h");
+    let path = CString::new(fifo.to_string_lossy().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o644) }, 0);
+    std::fs::write(
+        project.join("src/interrupted.cpp"),
+        "#include \"slow.h\"\nint main() { return 1; }\n",
+    )
+    .unwrap();
+    let result = in_dir(&project, || {
+        std::thread::scope(|s| {
+            s.spawn(|| kill_child("interrupted.cpp", &fifo));
+            build()
+        })
+    });
+    assert!(result.is_err());
+    // the previous object is still complete, and stale without fingerprint
+    assert_eq!(std::fs::read(&obj).unwrap(), complete);
+    assert!(!temp_path(&obj).exists());
+    assert!(!fingerprint_path(&obj).exists());
+    assert!(!temp_path(&fingerprint_path(&obj)).exists());
+    assert!(stale(&obj, "", "").is_some());
+}
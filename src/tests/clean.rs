@@ -0,0 +1,82 @@
+use super::{in_dir, temp_settings};
+use crate::cmd::{clean, CleanFlags};
+use std::path::Path;
+
+fn flags(profile: Option<&str>, packages: &[&str], dry_run: bool) -> CleanFlags {
+    CleanFlags {
+        profile: profile.map(str::to_string),
+        packages: packages.iter().map(|p| p.to_string()).collect(),
+        dry_run,
+    }
+}
+
+/// Create the folders `dirs` in `root`, return a function telling which of
+/// them still exist
+fn create(root: &Path, dirs: &[&'static str]) -> impl Fn() -> Vec<&'static str> {
+    for d in dirs {
+        std::fs::create_dir_all(root.join(d)).unwrap();
+    }
+    let (root, dirs) = (root.to_path_buf(), dirs.to_vec());
+    move || {
+        dirs.iter()
+            .copied()
+            .filter(|d| root.join(d).exists())
+            .collect()
+    }
+}
+
+#[test]
+fn clean_the_project_or_one_profile() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = temp_settings(dir.path(), &[]);
+    let existing = create(dir.path(), &["target/dev/src", "target/release/src", "src"]);
+    in_dir(dir.path(), || {
+        clean(&settings, &flags(Some("dev"), &[], true)).unwrap();
+        assert_eq!(existing(), ["target/dev/src", "target/release/src", "src"]);
+        clean(&settings, &flags(Some("dev"), &[], false)).unwrap();
+        assert_eq!(existing(), ["target/release/src", "src"]);
+        // nothing to clean
+        clean(&settings, &flags(Some("asan"), &[], false)).unwrap();
+        clean(&settings, &flags(None, &[], true)).unwrap();
+        assert_eq!(existing(), ["target/release/src", "src"]);
+        clean(&settings, &flags(None, &[], false)).unwrap();
+        assert_eq!(existing(), ["src"]);
+        assert!(clean(&settings, &flags(Some("../src"), &[], false)).is_err());
+    });
+}
+
+#[test]
+fn clean_the_objects_of_dependencies() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = temp_settings(dir.path(), &[]);
+    let existing = create(
+        dir.path(),
+        &[
+            "cache/build/gtest_1.10.0/dev",
+            "cache/build/gtest_1.11.0/dev",
+            "cache/build/fmt_9.1.0/dev",
+            "cache/src/gtest_1.11.0_0123456789abcdef",
+        ],
+    );
+    clean(&settings, &flags(None, &["gtest@1.11.0"], true)).unwrap();
+    assert_eq!(existing().len(), 4);
+    clean(&settings, &flags(None, &["gtest@1.11.0"], false)).unwrap();
+    assert_eq!(
+        existing(),
+        [
+            "cache/build/gtest_1.10.0/dev",
+            "cache/build/fmt_9.1.0/dev",
+            "cache/src/gtest_1.11.0_0123456789abcdef",
+        ]
+    );
+    clean(&settings, &flags(None, &["gtest"], false)).unwrap();
+    assert_eq!(
+        existing(),
+        [
+            "cache/build/fmt_9.1.0/dev",
+            "cache/src/gtest_1.11.0_0123456789abcdef",
+        ]
+    );
+    let err = clean(&settings, &flags(None, &["gtest"], false)).unwrap_err();
+    assert_eq!(err.to_string(), "no object of gtest in the cache");
+}
@@ -0,0 +1,148 @@
+use super::{build_flags, in_dir, read_package, read_toml, temp_settings, write_files};
+use crate::{cmd::compile, common::types::BuildSystem};
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// True if `program` can be launched, the tests using it are skipped
+/// otherwise
+fn available(program: &str) -> bool {
+    Command::new(program)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// `sum(a, b)` in a C library, `src/sum.c` and `include/sum.h` in `dir`.
+/// The library adds `OFFSET`, given by the flags of the package.
+fn write_sum(dir: &Path, toml: &str, build_files: &[(&str, &str)]) {
+    write_files(
+        dir,
+        &[
+            ("chataigne.toml", toml),
+            (
+                "include/sum.h",
+                "#ifdef __cplusplus\nextern \"C\"\n#endif\nint sum(int a, int b);\n",
+            ),
+            (
+                "src/sum.c",
+                "#include \"sum.h\"\nint sum(int a, int b) { return a + b + OFFSET; }\n",
+            ),
+        ],
+    );
+    write_files(dir, build_files);
+}
+
+/// Build in `dir` a project using the library `sum` in `lib` with the
+/// `profile`, run it and return its exit code
+fn build_and_run(dir: &Path, lib: &Path, profile: &str) -> Option<i32> {
+    let project = dir.join("app");
+    write_files(
+        &project,
+        &[
+            (
+                "chataigne.toml",
+                &format!(
+                    "[package]\nname=\"app\"\nversion=\"0.1.0\"\n[dependencies]\nsum={{path=\"{}\"}}\n",
+                    lib.display()
+                ),
+            ),
+            (
+                "src/main.cpp",
+                "#include <sum.h>\nint main() { return sum(2, 3); }\n",
+            ),
+        ],
+    );
+    let settings = temp_settings(dir, &[]);
+    in_dir(&project, || {
+        compile(&read_package(&project), &settings, &build_flags(profile)).unwrap();
+    });
+    let binary = project.join("target").join(profile).join("app");
+    Command::new(binary).status().unwrap().code()
+}
+
+#[test]
+fn cmake_library_is_built_and_linked() {
+    if !available("cmake") {
+        eprintln!("cmake not found, test skipped");
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let lib = dir.path().join("sum");
+    write_sum(
+        &lib,
+        "[package]\nname=\"sum\"\nversion=\"1.0.0\"\n[lib]\nbuild=\"cmake\"\ndefines=[\"OFFSET=1\"]\n",
+        &[(
+            "CMakeLists.txt",
+            "cmake_minimum_required(VERSION 3.10)\nproject(sum C)\n\
+             add_library(sum src/sum.c)\ntarget_include_directories(sum PRIVATE include)\n\
+             install(TARGETS sum ARCHIVE DESTINATION lib)\ninstall(FILES include/sum.h DESTINATION include)\n",
+        )],
+    );
+    assert_eq!(build_and_run(dir.path(), &lib, "dev"), Some(6));
+    let log = std::fs::read_to_string(dir.path().join("cache/build/sum_1.0.0/dev/build.log"));
+    assert!(log.unwrap().contains("-DCMAKE_BUILD_TYPE=Debug"));
+}
+
+#[test]
+fn make_library_is_built_and_linked() {
+    if !available("make") {
+        eprintln!("make not found, test skipped");
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let lib = dir.path().join("sum");
+    // the flags of the Makefile are overridden by the ones of the package
+    write_sum(
+        &lib,
+        "[package]\nname=\"sum\"\nversion=\"1.0.0\"\n[lib]\nbuild=\"make\"\nheaders=[\"include\"]\ndefines=[\"OFFSET=2\"]\n",
+        &[(
+            "Makefile",
+            "CFLAGS = -DOFFSET=100\nlibsum.a: src/sum.o\n\tar rcs $@ $^\nsrc/sum.o: src/sum.c\n\t$(CC) $(CFLAGS) -Iinclude -c $< -o $@\n",
+        )],
+    );
+    assert_eq!(build_and_run(dir.path(), &lib, "release"), Some(7));
+    let work = dir.path().join("cache/build/sum_1.0.0/release/src");
+    assert!(work.join("libsum.a").is_file());
+}
+
+#[test]
+fn cmake_options() {
+    let package = read_toml(
+        r#"
+[package]
+name="absl"
+version="20230802.0.0"
+
+[lib]
+build="cmake"
+cmake={variables={ABSL_PROPAGATE_CXX_STD="ON"}, targets=["absl_strings"]}
+"#,
+    );
+    let lib = package.get_lib().unwrap();
+    assert_eq!(lib.build, Some(BuildSystem::CMake));
+    assert_eq!(lib.cmake.variables["ABSL_PROPAGATE_CXX_STD"], "ON");
+    assert_eq!(lib.cmake.targets, ["absl_strings"]);
+}
+
+#[test]
+fn make_options() {
+    let package = read_toml(
+        r#"
+[package]
+name="zlib"
+version="1.3.0"
+
+[lib]
+build="autotools"
+make={configure=["--static"], targets=["libz.a"]}
+"#,
+    );
+    let lib = package.get_lib().unwrap();
+    assert_eq!(lib.build, Some(BuildSystem::Autotools));
+    assert_eq!(lib.make.configure, ["--static"]);
+    assert_eq!(lib.make.targets, ["libz.a"]);
+}
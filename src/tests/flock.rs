@@ -0,0 +1,20 @@
+use super::temp_settings;
+use crate::common::flock::CacheLock;
+
+#[test]
+fn lock_is_exclusive_until_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = temp_settings(dir.path(), &[]);
+    let lock = CacheLock::acquire(&settings, "build_gtest_1.11.0", "gtest").unwrap();
+    assert!(CacheLock::try_acquire(&settings, "build_gtest_1.11.0")
+        .unwrap()
+        .is_none());
+    // other resources aren't locked
+    assert!(CacheLock::try_acquire(&settings, "build_fmt_9.1.0")
+        .unwrap()
+        .is_some());
+    drop(lock);
+    assert!(CacheLock::try_acquire(&settings, "build_gtest_1.11.0")
+        .unwrap()
+        .is_some());
+}
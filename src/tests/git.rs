@@ -0,0 +1,283 @@
+use super::temp_settings;
+use crate::{
+    cmd::{
+        checkout_git_dependency,
+        git::{apply_patches, source_dir_name},
+        git_fetch, git_head,
+    },
+    common::types::{DepVal, GitTarget, Package, SrcVal},
+};
+use std::{path::Path, process::Command};
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@test"])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {args:?} failed");
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Commit a `version` file containing `content` in the working repository
+/// `work`, tag it and push it to its bare remote. Return the commit.
+fn release(work: &Path, content: &str) -> String {
+    std::fs::write(work.join("version"), content).unwrap();
+    git(work, &["add", "version"]);
+    git(work, &["commit", "-q", "-m", content]);
+    git(work, &["tag", content]);
+    git(work, &["push", "-q", "--tags", "origin", "HEAD:main"]);
+    git(work, &["rev-parse", "HEAD"])
+}
+
+/// Create a bare repository and a working repository pushing into it,
+/// return the url of the bare one.
+fn remote(root: &Path) -> String {
+    let bare = root.join("remote.git");
+    std::fs::create_dir(&bare).unwrap();
+    git(&bare, &["init", "-q", "--bare", "-b", "main"]);
+    let work = root.join("work");
+    std::fs::create_dir(&work).unwrap();
+    git(&work, &["init", "-q", "-b", "main"]);
+    git(&work, &["remote", "add", "origin", bare.to_str().unwrap()]);
+    format!("file://{}", bare.display())
+}
+
+fn target(url: &str) -> GitTarget {
+    GitTarget {
+        git: url.to_string(),
+        ..Default::default()
+    }
+}
+
+fn version(clone: &Path) -> String {
+    std::fs::read_to_string(clone.join("version")).unwrap()
+}
+
+#[test]
+fn existing_clone_fetches_a_new_tag() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = remote(dir.path());
+    let work = dir.path().join("work");
+    release(&work, "v1");
+    let dest = dir.path().join("clone");
+    let mut t = target(&url);
+    t.tag = Some("v1".into());
+    git_fetch(&t, &dest).unwrap();
+    assert_eq!(version(&dest), "v1");
+
+    release(&work, "v2");
+    t.tag = Some("v2".into());
+    git_fetch(&t, &dest).unwrap();
+    assert_eq!(version(&dest), "v2");
+}
+
+#[test]
+fn existing_clone_fetches_a_missing_rev() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = remote(dir.path());
+    let work = dir.path().join("work");
+    release(&work, "v1");
+    let dest = dir.path().join("clone");
+    git_fetch(&target(&url), &dest).unwrap();
+
+    let commit = release(&work, "v2");
+    let mut t = target(&url);
+    t.rev = Some(commit.clone());
+    git_fetch(&t, &dest).unwrap();
+    assert_eq!(git_head(&dest).unwrap(), commit);
+}
+
+#[test]
+fn shallow_clone_of_a_branch() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = remote(dir.path());
+    let work = dir.path().join("work");
+    release(&work, "v1");
+    release(&work, "v2");
+    let dest = dir.path().join("clone");
+    let mut t = target(&url);
+    t.branch = Some("main".into());
+    t.shallow = true;
+    git_fetch(&t, &dest).unwrap();
+    assert_eq!(version(&dest), "v2");
+    assert!(dest.join(".git").join("shallow").is_file());
+}
+
+#[test]
+fn failed_clone_leaves_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = remote(dir.path());
+    release(&dir.path().join("work"), "v1");
+    let clones = dir.path().join("clones");
+    std::fs::create_dir(&clones).unwrap();
+    let mut t = target(&url);
+    t.tag = Some("v3".into());
+    let err = git_fetch(&t, &clones.join("clone")).unwrap_err();
+    assert!(err.to_string().contains("tag v3"), "{err}");
+    assert_eq!(std::fs::read_dir(&clones).unwrap().count(), 0);
+
+    t.branch = Some("main".into());
+    assert!(git_fetch(&t, &clones.join("clone")).is_err());
+}
+
+#[test]
+fn patches_are_relative_to_the_package_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("1.0.0.toml");
+    std::fs::write(
+        &file,
+        r#"
+[package]
+name="zlib"
+version="1.0.0"
+src={git="https://example.com/zlib", patches=["fix-gcc13.patch"]}
+
+[dependencies]
+fmt={git="https://example.com/fmt", patches=["patches/fmt.patch"]}
+
+[profile.asan.dependencies]
+asan={git="https://example.com/asan", patches=["asan.patch"]}
+
+[test.dependencies]
+gtest={git="https://example.com/gtest", patches=["gtest.patch"]}
+"#,
+    )
+    .unwrap();
+    let package = Package::read(Some(file.to_string_lossy().to_string())).unwrap();
+    match &package.pkg_description.src {
+        Some(SrcVal::Git(src)) => assert_eq!(src.patches, [dir.path().join("fix-gcc13.patch")]),
+        _ => panic!("git source expected"),
+    }
+    match &package.dependencies.as_ref().unwrap()["fmt"] {
+        DepVal::Git(git) => assert_eq!(git.patches, [dir.path().join("patches/fmt.patch")]),
+        _ => panic!("git dependency expected"),
+    }
+    for (profile, name) in [("asan", "asan"), ("test", "gtest")] {
+        match &package.profiles[profile].dependencies[name] {
+            DepVal::Git(git) => {
+                assert_eq!(git.patches, [dir.path().join(format!("{name}.patch"))])
+            }
+            _ => panic!("git dependency expected"),
+        }
+    }
+}
+
+#[test]
+fn patches_are_applied_to_the_checkout() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = remote(dir.path());
+    release(&dir.path().join("work"), "v1");
+    let patch = |name: &str, from: &str, to: &str| {
+        let path = dir.path().join(name);
+        let diff = format!(
+            "--- a/version\n+++ b/version\n@@ -1 +1 @@\n-{from}\n\\ No newline at end of file\n+{to}\n\\ No newline at end of file\n"
+        );
+        std::fs::write(&path, diff).unwrap();
+        path
+    };
+
+    let clone = dir.path().join("clone");
+    let mut t = target(&url);
+    git_fetch(&t, &clone).unwrap();
+    t.patches = vec![patch("fix.patch", "v1", "v1-fixed")];
+    apply_patches(&t.patches, &t.git, &clone).unwrap();
+    assert_eq!(version(&clone), "v1-fixed");
+
+    let other = dir.path().join("other");
+    git_fetch(&t, &other).unwrap();
+    t.patches = vec![patch("old.patch", "v0", "v0-fixed")];
+    let err = apply_patches(&t.patches, &t.git, &other).unwrap_err();
+    assert!(err.to_string().contains("doesn't apply"), "{err}");
+    assert_eq!(version(&other), "v1");
+}
+
+#[test]
+fn each_version_and_revision_has_its_own_source_folder() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = "https://example.com/gtest.git";
+    let tagged = |tag: &str| GitTarget {
+        tag: Some(tag.to_string()),
+        ..target(url)
+    };
+    let v1 = source_dir_name("gtest_1.10.0", &tagged("v1.10.0")).unwrap();
+    assert!(v1.starts_with("gtest_1.10.0_"), "{v1}");
+    assert_eq!(
+        v1,
+        source_dir_name("gtest_1.10.0", &tagged("v1.10.0")).unwrap()
+    );
+    let mut names = vec![
+        v1,
+        source_dir_name("gtest_1.11.0", &tagged("v1.11.0")).unwrap(),
+        source_dir_name("gtest_1.10.0", &tagged("v1.10.1")).unwrap(),
+        source_dir_name("gtest_1.10.0", &target(url)).unwrap(),
+        source_dir_name(
+            "gtest_1.10.0",
+            &GitTarget {
+                rev: Some("e2239ee".to_string()),
+                ..target(url)
+            },
+        )
+        .unwrap(),
+    ];
+    let patch = dir.path().join("fix.patch");
+    std::fs::write(&patch, "first").unwrap();
+    let mut patched = tagged("v1.10.0");
+    patched.patches = vec![patch.clone()];
+    names.push(source_dir_name("gtest_1.10.0", &patched).unwrap());
+    std::fs::write(&patch, "second").unwrap();
+    names.push(source_dir_name("gtest_1.10.0", &patched).unwrap());
+    let count = names.len();
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), count, "{names:?}");
+}
+
+#[test]
+fn git_dependency_at_a_rev_a_tag_or_a_branch() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = remote(dir.path());
+    let work = dir.path().join("work");
+    let v1 = release(&work, "v1");
+    release(&work, "v2");
+    // a `dev` branch ahead of `main`
+    git(&work, &["checkout", "-q", "-b", "dev"]);
+    std::fs::write(work.join("version"), "v3").unwrap();
+    git(&work, &["commit", "-q", "-a", "-m", "v3"]);
+    git(&work, &["push", "-q", "origin", "HEAD:dev"]);
+
+    let settings = temp_settings(dir.path(), &[]);
+    let checkout = |t: GitTarget| checkout_git_dependency("dep", &t, &settings).unwrap();
+    let at_rev = checkout(GitTarget {
+        rev: Some(v1.clone()),
+        ..target(&url)
+    });
+    let at_tag = checkout(GitTarget {
+        tag: Some("v2".into()),
+        ..target(&url)
+    });
+    let at_branch = checkout(GitTarget {
+        branch: Some("dev".into()),
+        ..target(&url)
+    });
+    let head = checkout(target(&url));
+    assert_eq!(version(&at_rev), "v1");
+    assert_eq!(git_head(&at_rev).unwrap(), v1);
+    assert_eq!(version(&at_tag), "v2");
+    assert_eq!(version(&at_branch), "v3");
+    assert_eq!(version(&head), "v2");
+    for path in [&at_rev, &at_tag, &at_branch, &head] {
+        assert!(path.starts_with(settings.cache_dir.join("src")));
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("dep_git_"), "{name}");
+    }
+    // checked out once, an existing checkout is used as is
+    assert_eq!(
+        checkout(GitTarget {
+            tag: Some("v2".into()),
+            ..target(&url)
+        }),
+        at_tag
+    );
+}
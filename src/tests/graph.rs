@@ -0,0 +1,46 @@
+use super::{read_package, test_settings, write_lib};
+use crate::common::graph::DepGraph;
+
+#[test]
+fn diamond_builds_each_package_once() {
+    let dir = tempfile::tempdir().unwrap();
+    write_lib(dir.path(), "d", &[], &[]);
+    write_lib(dir.path(), "b", &["d"], &[]);
+    write_lib(dir.path(), "c", &["d"], &[]);
+    write_lib(dir.path(), "a", &["b", "c"], &[]);
+    let graph = DepGraph::build(
+        &read_package(&dir.path().join("a")),
+        &test_settings(),
+        "release",
+        None,
+    )
+    .unwrap();
+    let names: Vec<&str> = graph
+        .nodes
+        .iter()
+        .map(|n| n.package.pkg_description.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["d", "b", "c"]);
+    let link: Vec<usize> = graph.link_order().collect();
+    assert_eq!(link, vec![2, 1, 0]);
+}
+
+#[test]
+fn cycle_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    write_lib(dir.path(), "b", &["c"], &[]);
+    write_lib(dir.path(), "c", &["b"], &[]);
+    write_lib(dir.path(), "a", &["b"], &[]);
+    let err = DepGraph::build(
+        &read_package(&dir.path().join("a")),
+        &test_settings(),
+        "release",
+        None,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "cyclic dependency: b 1.0.0 -> c 1.0.0 -> b 1.0.0"
+    );
+}
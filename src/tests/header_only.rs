@@ -0,0 +1,67 @@
+use super::{build_flags, in_dir, read_package, read_toml, temp_settings, write_files};
+use crate::{cmd::compile, common::types::LibKind};
+use std::{path::Path, process::Command};
+
+#[test]
+fn header_only_library_gives_its_headers_folders() {
+    let dir = tempfile::tempdir().unwrap();
+    let json = dir.path().join("json");
+    write_files(
+        &json,
+        &[
+            (
+                "chataigne.toml",
+                "[package]\nname=\"json\"\nversion=\"3.11.0\"\n[lib]\nkind=\"header-only\"\nheaders=[\"include\"]\ndefines=[\"JSON_VALUE=5\"]\n",
+            ),
+            ("include/json/json.h", "inline int parse() { return JSON_VALUE; }\n"),
+        ],
+    );
+    let project = dir.path().join("app");
+    write_files(
+        &project,
+        &[
+            (
+                "chataigne.toml",
+                &format!(
+                    "[package]\nname=\"app\"\nversion=\"0.1.0\"\n[dependencies]\njson={{path=\"{}\"}}\n",
+                    json.display()
+                ),
+            ),
+            (
+                "src/main.cpp",
+                "#include <json/json.h>\nint main() { return parse(); }\n",
+            ),
+        ],
+    );
+    let settings = temp_settings(dir.path(), &[]);
+    in_dir(&project, || {
+        compile(&read_package(&project), &settings, &build_flags("dev")).unwrap();
+    });
+    let status = Command::new(project.join("target/dev/app"))
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(5));
+}
+
+#[test]
+fn header_only_flags_are_public() {
+    let package = read_toml(
+        r#"
+[package]
+name="json"
+version="3.11.0"
+
+[lib]
+kind="header-only"
+headers=["include"]
+defines=["JSON_NOEXCEPTION"]
+cxxflags=["-std=c++17"]
+"#,
+    );
+    let lib = package.get_lib().unwrap();
+    assert_eq!(lib.kind, LibKind::HeaderOnly);
+    assert_eq!(
+        lib.flags.compile_args(Path::new("main.cpp"), false),
+        ["-DJSON_NOEXCEPTION", "-std=c++17"]
+    );
+}
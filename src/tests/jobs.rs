@@ -0,0 +1,69 @@
+use super::{build_flags, in_dir, read_package, temp_settings, write_files};
+use crate::cmd::{compile, jobs::Scheduler};
+use anyhow::bail;
+use std::sync::Mutex;
+
+/// Run 3 tasks on one job, the first two fail. Return the errors and the
+/// tasks run.
+fn run_failing(keep_going: bool) -> (Vec<String>, Vec<u32>) {
+    let scheduler = Scheduler::new(1, keep_going).unwrap();
+    let ran = Mutex::new(vec![]);
+    let errors = scheduler.run(vec![1, 2, 3], |task| {
+        ran.lock().unwrap().push(task);
+        if task < 3 {
+            bail!("task {task} failed")
+        }
+        Ok(())
+    });
+    let errors = errors.iter().map(|e| e.to_string()).collect();
+    (errors, ran.into_inner().unwrap())
+}
+
+#[test]
+fn stop_on_the_first_error() {
+    let (errors, ran) = run_failing(false);
+    assert_eq!(errors, ["task 1 failed"]);
+    assert_eq!(ran, [1]);
+}
+
+#[test]
+fn keep_going_reports_all_the_errors() {
+    let (errors, ran) = run_failing(true);
+    assert_eq!(errors, ["task 1 failed", "task 2 failed"]);
+    assert_eq!(ran, [1, 2, 3]);
+}
+
+#[test]
+fn keep_going_build_reports_each_failed_unit() {
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().join("app");
+    write_files(
+        &project,
+        &[
+            (
+                "chataigne.toml",
+                "[package]\nname=\"app\"\nversion=\"0.1.0\"\n",
+            ),
+            ("src/a.cpp", "int a() { return first; }\n"),
+            ("src/b.cpp", "int b() { return second; }\n"),
+            ("src/main.cpp", "int main() { return 0; }\n"),
+        ],
+    );
+    let settings = temp_settings(dir.path(), &[]);
+    let build = |keep_going: bool| {
+        let mut flags = build_flags("dev");
+        flags.keep_going = keep_going;
+        flags.jobs = Some(1);
+        let err = in_dir(&project, || {
+            compile(&read_package(&project), &settings, &flags).unwrap_err()
+        });
+        format!("{err:#}")
+    };
+    let err = build(false);
+    assert!(err.contains("'first' was not declared"), "{err}");
+    assert!(!err.contains("'second' was not declared"), "{err}");
+    let err = build(true);
+    assert!(err.starts_with("2 unit(s) failed to build"), "{err}");
+    assert!(err.contains("'first' was not declared"), "{err}");
+    assert!(err.contains("'second' was not declared"), "{err}");
+}
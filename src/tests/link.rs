@@ -0,0 +1,69 @@
+use super::{read_package, test_settings, write_lib};
+use crate::{
+    cmd::gcc::{archive_command, archive_path, link_command},
+    common::{graph::DepGraph, types::Flags},
+};
+use std::path::{Path, PathBuf};
+
+/// Arguments of the archive and link commands of the package `name` in
+/// `root`, the package is read again each time.
+fn command_lines(root: &Path, name: &str) -> Vec<Vec<String>> {
+    let package = read_package(&root.join(name));
+    let settings = test_settings();
+    let graph = DepGraph::build(&package, &settings, "release", None).unwrap();
+    let profile = package.profile("release").unwrap();
+    let mut flags = vec![&profile.flags];
+    let mut inputs = vec![vec![PathBuf::from("main.o")]];
+    let mut commands = vec![];
+    for i in graph.link_order() {
+        let package = &graph.nodes[i].package;
+        let archive = archive_path(package, &settings, "release");
+        let objects = [PathBuf::from("a.o"), PathBuf::from("b.o")];
+        commands.push(archive_command(&archive, &objects));
+        inputs.push(vec![archive]);
+        flags.push(&package.get_lib().unwrap().flags);
+    }
+    commands.push(link_command(Path::new("app"), &flags, &inputs).unwrap());
+    commands
+        .iter()
+        .map(|c| {
+            std::iter::once(c.get_program())
+                .chain(c.get_args())
+                .map(|a| a.to_string_lossy().to_string())
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn archive_and_link_commands_are_the_same_on_every_build() {
+    let dir = tempfile::tempdir().unwrap();
+    write_lib(dir.path(), "d", &[], &["d_sys"]);
+    write_lib(dir.path(), "b", &["d"], &["b_sys"]);
+    write_lib(dir.path(), "c", &["d"], &["c_sys"]);
+    write_lib(dir.path(), "e", &[], &["e_sys"]);
+    write_lib(dir.path(), "a", &["e", "c", "b"], &["a_sys"]);
+    let first = command_lines(dir.path(), "a");
+    for _ in 0..10 {
+        assert_eq!(command_lines(dir.path(), "a"), first);
+    }
+    // the root, then each library before the ones it depends on
+    let link = first.last().unwrap();
+    let libs: Vec<&str> = link.iter().filter_map(|a| a.strip_prefix("-l")).collect();
+    assert_eq!(libs, ["a_sys", "e_sys", "c_sys", "b_sys", "d_sys"]);
+}
+
+#[test]
+fn libraries_keep_their_last_occurrence_in_the_link() {
+    let flags = |libs: &[&str]| Flags {
+        libs: libs.iter().map(|l| l.to_string()).collect(),
+        ..Default::default()
+    };
+    // the root needs crypto, then a dependency needs ssl and crypto
+    let root = flags(&["crypto"]);
+    let dep = flags(&["ssl", "crypto", "/opt/lib/libz.a"]);
+    assert_eq!(
+        Flags::link_libs(&[&root, &dep]),
+        ["-lssl", "-lcrypto", "/opt/lib/libz.a"]
+    );
+}
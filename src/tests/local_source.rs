@@ -0,0 +1,65 @@
+use super::{read_package, temp_settings, write_files};
+use crate::{
+    cmd::git::checkout_dependency,
+    common::types::{DepVal, Dependency},
+};
+use std::path::{Path, PathBuf};
+
+/// Folder of the sources of the layer package `zlib` in `layer`, with
+/// `src = { path = "<path>" }`
+fn sources(dir: &Path, layer: &Path, path: &str) -> anyhow::Result<PathBuf> {
+    let toml =
+        format!("[package]\nname=\"zlib\"\nversion=\"1.3.0\"\nsrc={{path=\"{path}\"}}\n[lib]\n");
+    write_files(&layer.join("zlib"), &[("chataigne.toml", &toml)]);
+    let dependency = Dependency {
+        name: "zlib".to_string(),
+        desc: DepVal::Version("1".to_string()),
+    };
+    let package = read_package(&layer.join("zlib"));
+    checkout_dependency(&dependency, &package, &temp_settings(dir, &[layer]))
+}
+
+#[test]
+fn relative_sources_from_the_package_file_then_the_layer() {
+    let dir = tempfile::tempdir().unwrap();
+    let layer = dir.path().join("layer");
+    for folder in ["zlib/code", "vendor/zlib", "zlib/vendor/zlib"] {
+        std::fs::create_dir_all(layer.join(folder)).unwrap();
+    }
+    let canonical = |p: &str| std::fs::canonicalize(layer.join(p)).unwrap();
+    let path = sources(dir.path(), &layer, "code").unwrap();
+    assert_eq!(path, canonical("zlib/code"));
+    // the folder of the package file comes first
+    let path = sources(dir.path(), &layer, "vendor/zlib").unwrap();
+    assert_eq!(path, canonical("zlib/vendor/zlib"));
+    std::fs::remove_dir_all(layer.join("zlib/vendor")).unwrap();
+    let path = sources(dir.path(), &layer, "vendor/zlib").unwrap();
+    assert_eq!(path, canonical("vendor/zlib"));
+    // nothing is copied in the cache
+    assert!(!dir.path().join("cache/src").exists());
+}
+
+#[test]
+fn absolute_sources() {
+    let dir = tempfile::tempdir().unwrap();
+    let layer = dir.path().join("layer");
+    let code = dir.path().join("zlib-1.3");
+    std::fs::create_dir_all(&code).unwrap();
+    let path = sources(dir.path(), &layer, &code.to_string_lossy()).unwrap();
+    assert_eq!(path, std::fs::canonicalize(&code).unwrap());
+}
+
+#[test]
+fn missing_sources() {
+    let dir = tempfile::tempdir().unwrap();
+    let layer = dir.path().join("layer");
+    let err = sources(dir.path(), &layer, "code").unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with("sources of zlib not found at code"),
+        "{err}"
+    );
+    let missing = dir.path().join("missing");
+    let err = sources(dir.path(), &layer, &missing.to_string_lossy()).unwrap_err();
+    assert!(err.to_string().contains("not found"), "{err}");
+}
@@ -0,0 +1,105 @@
+use super::test_settings;
+use crate::{
+    common::{
+        checksum::temp_path,
+        lockfile::{self, lock_path, LockFile, LockedPackage},
+        types::{GitTarget, Package},
+    },
+    settings::Layer,
+};
+
+fn locked(name: &str, version: &str) -> LockedPackage {
+    LockedPackage {
+        name: name.to_string(),
+        version: version.to_string(),
+        source: String::from("layer+layers"),
+        layer_commit: None,
+        src_commit: Some(String::from("e2239ee")),
+        checksum: String::from("0000"),
+    }
+}
+
+#[test]
+fn lock_roundtrip_and_merge() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = dir.path().join("chataigne.toml");
+    std::fs::write(&manifest, "[package]\nname=\"root\"\nversion=\"0.1.0\"\n").unwrap();
+    let root = Package::read(Some(manifest.to_string_lossy().to_string())).unwrap();
+
+    let test_lock = LockFile::new(
+        String::from("m1"),
+        vec![locked("gtest", "1.11.0"), locked("fmt", "9.0.0")],
+        None,
+    );
+    test_lock.save(&root).unwrap();
+    let loaded = LockFile::load(&root, "m1").unwrap().unwrap();
+    assert_eq!(loaded, test_lock);
+    assert_eq!(loaded.package[0].name, "fmt");
+
+    // a release build doesn't drop the test dependencies
+    let release_lock = LockFile::new(
+        String::from("m1"),
+        vec![locked("fmt", "9.0.0")],
+        Some(&loaded),
+    );
+    assert_eq!(release_lock, loaded);
+
+    // but a new package file starts from scratch
+    let new_lock = LockFile::new(
+        String::from("m2"),
+        vec![locked("fmt", "9.1.0")],
+        Some(&loaded),
+    );
+    assert_eq!(new_lock.package, vec![locked("fmt", "9.1.0")]);
+}
+
+#[test]
+fn outdated_lock_is_not_loaded() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = dir.path().join("chataigne.toml");
+    std::fs::write(&manifest, "[package]\nname=\"root\"\nversion=\"0.1.0\"\n").unwrap();
+    let root = Package::read(Some(manifest.to_string_lossy().to_string())).unwrap();
+
+    let hash = lockfile::manifest_hash(&root).unwrap();
+    LockFile::new(hash.clone(), vec![locked("fmt", "9.0.0")], None)
+        .save(&root)
+        .unwrap();
+    assert!(!temp_path(&lock_path(&root)).exists());
+    assert!(LockFile::load(&root, &hash).unwrap().is_some());
+
+    // the package file changed since the lock was written
+    std::fs::write(&manifest, "[package]\nname=\"root\"\nversion=\"0.2.0\"\n").unwrap();
+    let hash = lockfile::manifest_hash(&root).unwrap();
+    let previous = LockFile::load(&root, &hash).unwrap();
+    assert!(previous.is_none());
+    let err = lockfile::update(&root, hash, vec![locked("fmt", "9.0.0")], previous, true);
+    assert!(err.is_err());
+}
+
+#[test]
+fn locked_layers_are_pinned_in_a_copy_of_the_settings() {
+    let mut settings = test_settings();
+    for url in ["https://example.com/a.git", "https://example.com/b.git"] {
+        settings.layers.push(Layer::Git(GitTarget {
+            git: url.to_string(),
+            ..Default::default()
+        }));
+    }
+    let mut package = locked("gtest", "1.11.0");
+    package.source = String::from("layer+https://example.com/a.git");
+    package.layer_commit = Some(String::from("e2239ee"));
+    let lock = LockFile::new(String::from("m1"), vec![package], None);
+
+    let pinned = lock.pin_layers(&settings);
+    let revs: Vec<_> = pinned
+        .layers
+        .iter()
+        .map(|l| match l {
+            Layer::Git(git) => git.rev.clone(),
+            Layer::Dir(_) => None,
+        })
+        .collect();
+    assert_eq!(revs, [Some(String::from("e2239ee")), None]);
+    // the settings shared with the other builds are left as is
+    assert!(matches!(&settings.layers[0], Layer::Git(git) if git.rev.is_none()));
+}
@@ -1,13 +1,141 @@
 use crate::{
-    common::types::DepVal,
-    pkg::{self},
+    common::types::{BuildFlags, DepVal, LocalTarget, Package},
+    settings::{Layer, Settings},
 };
+use directories::ProjectDirs;
+use std::{panic::AssertUnwindSafe, path::Path, sync::Mutex};
+
+mod archive;
+mod build;
+mod cache;
+mod checksum;
+mod clean;
+mod external;
+mod flock;
+mod git;
+mod graph;
+mod header_only;
+mod jobs;
+mod link;
+mod local_source;
+mod lockfile;
+mod pkg_config;
+mod prebuilt;
+mod profile;
+mod resolver;
+mod runner;
+mod shared;
+
+/// Settings without any layer, that don't touch the user configuration
+fn test_settings() -> Settings {
+    Settings {
+        layers: vec![],
+        jobs: None,
+        max_cache_size: None,
+        cache_dir: ProjectDirs::from("com", "cppccn", "chataigne")
+            .unwrap()
+            .cache_dir()
+            .to_path_buf(),
+    }
+}
+
+/// Settings with the folder `layers` and their cache in `dir`, that don't
+/// touch the user configuration nor its cache
+fn temp_settings(dir: &Path, layers: &[&Path]) -> Settings {
+    Settings {
+        layers: layers
+            .iter()
+            .map(|l| {
+                Layer::Dir(LocalTarget {
+                    path: l.to_string_lossy().to_string(),
+                })
+            })
+            .collect(),
+        jobs: None,
+        max_cache_size: None,
+        cache_dir: dir.join("cache"),
+    }
+}
+
+/// Flags of `ch build` with the `profile`
+fn build_flags(profile: &str) -> BuildFlags {
+    BuildFlags {
+        profile: profile.to_string(),
+        keep_going: false,
+        explain: false,
+        jobs: None,
+        locked: false,
+    }
+}
+
+/// Taken by the tests changing the current directory, the builds write
+/// their outputs in the `target` folder of the current directory
+static CURRENT_DIR: Mutex<()> = Mutex::new(());
+
+/// Run `f` in the folder `dir`, then go back to the previous one
+fn in_dir<T>(dir: &Path, f: impl FnOnce() -> T) -> T {
+    let _lock = CURRENT_DIR.lock().unwrap_or_else(|e| e.into_inner());
+    let previous = std::env::current_dir().unwrap();
+    std::env::set_current_dir(dir).unwrap();
+    let ret = std::panic::catch_unwind(AssertUnwindSafe(f));
+    std::env::set_current_dir(previous).unwrap();
+    ret.unwrap_or_else(|e| std::panic::resume_unwind(e))
+}
+
+/// Write the files of a package in `dir`, each given by its path relative
+/// to `dir` and its content
+fn write_files(dir: &Path, files: &[(&str, &str)]) {
+    for (path, content) in files {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+}
+
+/// Write a library package `name` linked with the `libs` and depending on
+/// the packages `deps`, all in the directory `root`.
+fn write_lib(root: &Path, name: &str, deps: &[&str], libs: &[&str]) {
+    let mut toml = format!(
+        "[package]\nname=\"{name}\"\nversion=\"1.0.0\"\n[lib]\nlibs={libs:?}\n[dependencies]\n"
+    );
+    for d in deps {
+        toml.push_str(&format!("{d}={{path=\"{}\"}}\n", root.join(d).display()));
+    }
+    write_files(&root.join(name), &[("chataigne.toml", &toml)]);
+}
+
+/// Read the package file `toml`, written in a temporary folder
+fn try_read_toml(toml: &str) -> anyhow::Result<Package> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("chataigne.toml");
+    std::fs::write(&path, toml).unwrap();
+    Package::read(Some(path.to_string_lossy().to_string()))
+}
+
+fn read_toml(toml: &str) -> Package {
+    try_read_toml(toml).unwrap()
+}
+
+/// Read the package file of the package in `dir`
+fn read_package(dir: &Path) -> Package {
+    let path = dir.join("chataigne.toml");
+    Package::read(Some(path.to_string_lossy().to_string())).unwrap()
+}
 
 #[test]
 fn load_dep() {
-    let pkg_file = pkg::read(Some(String::from("src/tests/dep.toml"))).unwrap();
+    let pkg_file = Package::read(Some(String::from(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/tests/dep.toml"
+    ))))
+    .unwrap();
     assert!(matches!(
-        pkg_file.test.dependencies.unwrap().get("gtest").unwrap(),
+        pkg_file
+            .profile("test")
+            .unwrap()
+            .dependencies
+            .get("gtest")
+            .unwrap(),
         DepVal::Version(_)
     ));
     // todo: implement all other possibilities
@@ -0,0 +1,27 @@
+use crate::{
+    cmd::{pkg_config::module_specs, system_package},
+    common::types::SystemTarget,
+};
+
+#[test]
+fn version_requirements() {
+    assert_eq!(
+        module_specs("openssl", ">=1.1, <4").unwrap(),
+        ["openssl >= 1.1", "openssl < 4"]
+    );
+    assert_eq!(module_specs("zlib", "==1.2").unwrap(), ["zlib = 1.2"]);
+    assert!(module_specs("zlib", "^1.2").is_err());
+    assert!(module_specs("zlib", ">=").is_err());
+}
+
+#[test]
+fn missing_system_library() {
+    let target = SystemTarget {
+        system: "chataigne-missing-library".to_string(),
+        version: None,
+    };
+    match system_package("missing", &target) {
+        Ok(_) => panic!("found a missing library"),
+        Err(err) => assert!(format!("{err}").contains("not found"), "{err}"),
+    }
+}
@@ -0,0 +1,36 @@
+use super::read_toml;
+use crate::common::types::LibKind;
+
+#[test]
+fn prebuilt_by_arch() {
+    let package = read_toml(
+        r#"
+[package]
+name="sdk"
+version="1.0.0"
+
+[lib]
+kind="shared"
+
+[lib.prebuilt]
+static="lib/libsdk.a"
+shared="lib/libsdk.so"
+headers=["include"]
+arch.aarch64={static="lib/arm/libsdk.a", shared="lib/arm/libsdk.so", sha256="abc"}
+"#,
+    );
+    let prebuilt = package.get_lib().unwrap().prebuilt.as_ref().unwrap();
+    let arm = prebuilt.for_arch("aarch64");
+    assert_eq!(
+        arm.library(LibKind::Shared),
+        Some(("lib/arm/libsdk.so", LibKind::Shared))
+    );
+    assert_eq!(arm.sha256.as_deref(), Some("abc"));
+    assert_eq!(arm.headers.unwrap(), ["include"]);
+    let x86 = prebuilt.for_arch("x86_64");
+    assert_eq!(
+        x86.library(LibKind::Static),
+        Some(("lib/libsdk.a", LibKind::Static))
+    );
+    assert!(x86.sha256.is_none());
+}
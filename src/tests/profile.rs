@@ -0,0 +1,155 @@
+use super::{read_toml, try_read_toml};
+use crate::common::types::Profile;
+use std::path::Path;
+
+const PACKAGE: &str = r#"
+opt=["-Wall"]
+ignore=["**/bench.cpp"]
+
+[package]
+name="app"
+version="0.1.0"
+
+[dependencies]
+fmt="^9"
+
+[profile.dev]
+defines=["DEV"]
+
+[profile.test]
+dependencies={gtest="^1.11"}
+
+[profile.asan]
+cflags=["-fsanitize=address"]
+ignore=["**/fuzz.cpp"]
+
+[profile.fast]
+inherits="release"
+opt-level="3"
+"#;
+
+#[test]
+fn builtin_profiles() {
+    let package = read_toml(PACKAGE);
+    let release = package.profile("release").unwrap();
+    assert_eq!(
+        release.compile_args(Path::new("main.cpp")),
+        ["-O2", "-DNDEBUG", "-Wall"]
+    );
+    assert_eq!(release.ignore, ["**/bench.cpp"]);
+    let dev = package.profile("dev").unwrap();
+    assert_eq!(
+        dev.compile_args(Path::new("main.cpp")),
+        ["-O0", "-g", "-DDEV", "-Wall"]
+    );
+    let test = package.profile("test").unwrap();
+    assert_eq!(
+        test.compile_args(Path::new("main.cpp")),
+        dev.compile_args(Path::new("main.cpp"))
+    );
+    assert_eq!(test.ignore, ["**/main.cpp"]);
+    let mut deps: Vec<_> = test.dependencies.keys().collect();
+    deps.sort();
+    assert_eq!(deps, ["fmt", "gtest"]);
+}
+
+#[test]
+fn custom_profiles_inherit() {
+    let package = read_toml(PACKAGE);
+    let asan = package.profile("asan").unwrap();
+    assert_eq!(
+        asan.compile_args(Path::new("main.cpp")),
+        ["-O0", "-g", "-DDEV", "-Wall", "-fsanitize=address"]
+    );
+    assert_eq!(asan.ignore, ["**/fuzz.cpp"]);
+    let fast = package.profile("fast").unwrap();
+    assert_eq!(
+        fast.compile_args(Path::new("main.cpp")),
+        ["-O3", "-DNDEBUG", "-Wall"]
+    );
+    assert!(package.profile("nope").is_err());
+}
+
+const LIB: &str = r#"
+[package]
+name="lib"
+version="1.0.0"
+
+[lib]
+cflags=["-Wall", {value="-fopenmp", scope="public"}, {value="-flto", scope="link"}]
+cxxflags=[{value="-std=c++17", scope="public"}]
+defines=["LIB_BUILD", {value="LIB_SHARED", scope="public"}]
+ldflags=["-Wl,--as-needed"]
+libs=["pthread", "/opt/lib/libfoo.a"]
+opt=["-pthread"]
+"#;
+
+#[test]
+fn flags_scopes() {
+    let package = read_toml(LIB);
+    let flags = &package.get_lib().unwrap().flags;
+    assert_eq!(
+        flags.compile_args(Path::new("lib.cpp"), true),
+        [
+            "-DLIB_BUILD",
+            "-DLIB_SHARED",
+            "-Wall",
+            "-fopenmp",
+            "-pthread",
+            "-std=c++17"
+        ]
+    );
+    assert_eq!(
+        flags.compile_args(Path::new("main.cpp"), false),
+        ["-DLIB_SHARED", "-fopenmp", "-std=c++17"]
+    );
+    assert_eq!(
+        flags.compile_args(Path::new("main.c"), false),
+        ["-DLIB_SHARED", "-fopenmp"]
+    );
+    assert_eq!(flags.link_args(), ["-flto", "-Wl,--as-needed", "-pthread"]);
+    assert_eq!(flags.lib_args(), ["-lpthread", "/opt/lib/libfoo.a"]);
+}
+
+#[test]
+fn link_flags_only_have_the_link_scope() {
+    let toml = "[package]\nname=\"a\"\nversion=\"1.0.0\"\n";
+    let result = try_read_toml(&format!("libs=[{{value=\"m\", scope=\"public\"}}]\n{toml}"));
+    assert!(result.is_err());
+    let result = try_read_toml(&format!(
+        "defines=[{{value=\"A\", scope=\"link\"}}]\n{toml}"
+    ));
+    assert!(result.is_err());
+}
+
+#[test]
+fn profile_names_stay_in_target() {
+    for name in ["dev", "asan", "release-lto", "my_profile"] {
+        assert!(Profile::check_name(name).is_ok(), "{name}");
+    }
+    for name in ["", "/", "..", ".", "a/b", "../src", "dev "] {
+        assert!(Profile::check_name(name).is_err(), "{name}");
+    }
+}
+
+#[test]
+fn dependencies_get_the_profile_options_only() {
+    let package = read_toml(
+        r#"
+[package]
+name="hello"
+version="0.1.0"
+cflags=["-Wall"]
+
+[profile.release]
+defines=["FAST"]
+"#,
+    );
+    let release = package.profile("release").unwrap();
+    assert_eq!(
+        release.dependency_args(Path::new("lib.cpp")),
+        ["-O2", "-DNDEBUG", "-DFAST"]
+    );
+    let dev = package.profile("dev").unwrap();
+    assert_eq!(dev.dependency_args(Path::new("lib.cpp")), ["-O0", "-g"]);
+}
@@ -0,0 +1,74 @@
+use crate::common::types::LocalTarget;
+use crate::{
+    common::{graph::DepGraph, types::Package},
+    settings::Layer,
+};
+use std::path::Path;
+
+/// Write the package file of `name` at `version` in the layer `layer`, with
+/// the given `[dependencies]` section content.
+fn write_layer_pkg(layer: &Path, name: &str, version: &str, deps: &str) {
+    let dir = layer.join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join(format!("{version}.toml")),
+        format!("[package]\nname=\"{name}\"\nversion=\"{version}\"\n[lib]\n[dependencies]\n{deps}"),
+    )
+    .unwrap();
+}
+
+fn resolve(layer: &Path, root_deps: &str) -> anyhow::Result<Vec<String>> {
+    let root = layer.join("root.toml");
+    std::fs::write(
+        &root,
+        format!("[package]\nname=\"root\"\nversion=\"0.1.0\"\n[dependencies]\n{root_deps}"),
+    )
+    .unwrap();
+    let mut settings = super::test_settings();
+    settings.layers = vec![Layer::Dir(LocalTarget {
+        path: layer.to_string_lossy().to_string(),
+    })];
+    let root = Package::read(Some(root.to_string_lossy().to_string()))?;
+    let graph = DepGraph::build(&root, &settings, "release", None)?;
+    Ok(graph
+        .nodes
+        .iter()
+        .map(|n| {
+            format!(
+                "{} {}",
+                n.package.pkg_description.name, n.package.pkg_description.version
+            )
+        })
+        .collect())
+}
+
+#[test]
+fn highest_version_matching_all_requirements() {
+    let layer = tempfile::tempdir().unwrap();
+    for v in ["1.10.0", "1.11.0", "1.11.2", "1.12.0"] {
+        write_layer_pkg(layer.path(), "gtest", v, "");
+    }
+    write_layer_pkg(layer.path(), "foo", "1.0.0", "gtest=\"~1.11\"\n");
+    let nodes = resolve(layer.path(), "gtest=\"^1.10\"\nfoo=\"*\"\n").unwrap();
+    assert_eq!(nodes, vec!["gtest 1.11.2", "foo 1.0.0"]);
+}
+
+#[test]
+fn conflict_names_the_requirements() {
+    let layer = tempfile::tempdir().unwrap();
+    for v in ["1.10.0", "1.11.0"] {
+        write_layer_pkg(layer.path(), "gtest", v, "");
+    }
+    write_layer_pkg(layer.path(), "foo", "1.0.0", "gtest=\"=1.10.0\"\n");
+    let err = resolve(layer.path(), "foo=\"1\"\ngtest=\">=1.11, <2\"\n")
+        .err()
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        err,
+        "unable to find a version of gtest matching all the requirements:\n  \
+         foo 1.0.0 requires gtest =1.10.0\n  \
+         root 0.1.0 requires gtest >=1.11, <2\n\
+         available versions: 1.11.0, 1.10.0"
+    );
+}
@@ -0,0 +1,54 @@
+use super::{build_flags, in_dir, read_package, temp_settings, write_files};
+use crate::cmd::{compile, runner};
+use std::process::Command;
+
+const BROKEN: &str = "int main() { return missing; }\n";
+
+#[test]
+fn failed_gcc_keeps_its_diagnostic() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("main.cpp");
+    std::fs::write(&src, BROKEN).unwrap();
+    let mut cmd = Command::new("gcc");
+    cmd.arg("-c")
+        .arg(&src)
+        .arg("-o")
+        .arg(dir.path().join("main.o"));
+    let err = runner::run(cmd, Some(&src)).unwrap_err();
+    assert!(matches!(err.status, Some(code) if code != 0));
+    assert!(
+        err.stderr.contains("'missing' was not declared"),
+        "{}",
+        err.stderr
+    );
+    let message = err.to_string();
+    assert!(message.contains(&src.to_string_lossy().to_string()));
+    assert!(message.contains("gcc -c"));
+    assert!(message.contains("'missing' was not declared"));
+}
+
+#[test]
+fn build_fails_on_a_compile_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().join("app");
+    write_files(
+        &project,
+        &[
+            (
+                "chataigne.toml",
+                "[package]\nname=\"app\"\nversion=\"0.1.0\"\n",
+            ),
+            ("src/main.cpp", BROKEN),
+        ],
+    );
+    let settings = temp_settings(dir.path(), &[]);
+    let err = in_dir(&project, || {
+        compile(&read_package(&project), &settings, &build_flags("dev")).unwrap_err()
+    });
+    assert!(
+        format!("{err:#}").contains("'missing' was not declared"),
+        "{err:#}"
+    );
+    assert!(!project.join("target/dev/app").exists());
+    assert!(!project.join("target/dev/src/main.o").exists());
+}
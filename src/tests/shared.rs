@@ -0,0 +1,16 @@
+use super::read_toml;
+use crate::cmd::gcc::soname;
+
+#[test]
+fn soname_follows_the_compatible_versions() {
+    let soname = |version: &str| {
+        soname(&read_toml(&format!(
+            "[package]\nname=\"sdk\"\nversion=\"{version}\"\n[lib]\nkind=\"shared\"\n"
+        )))
+    };
+    assert_eq!(soname("0.3.2"), "libsdk.so.0.3");
+    assert_eq!(soname("0.4.0"), "libsdk.so.0.4");
+    assert_eq!(soname("1.2.3"), "libsdk.so.1");
+    assert_eq!(soname("1.9.0"), "libsdk.so.1");
+    assert_eq!(soname("2.0.0"), "libsdk.so.2");
+}